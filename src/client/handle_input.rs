@@ -1,12 +1,17 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
-use nalgebra::Rotation3;
+use nalgebra::{Rotation3, Vector3};
 use ndarray::Array3;
 use winit::{event::MouseButton, keyboard::KeyCode as Key, window::CursorGrabMode};
 
-use crate::common::{
-    component::{chunk, VoxelGrid, VoxelGridBundle},
-    ServerMessage,
+use crate::{
+    common::{
+        component::{
+            chunk, ChunkData, ChunkPos, LocalVoxelPos, ViewDistance, VoxelGrid, VoxelGridBundle,
+        },
+        ServerMessage, VoxelEdit,
+    },
+    util::oct_tree::Shape,
 };
 
 use super::{render::voxel::VoxelColor, Client};
@@ -102,6 +107,7 @@ impl Client<'_> {
             state.camera.pos += *state.camera.down() * move_dist;
         }
         if state.camera != old_camera {
+            self.camera_moved = true;
             self.render_commands
                 .push(super::render::RenderCommand::ViewUpdate(state.camera));
         }
@@ -122,6 +128,67 @@ impl Client<'_> {
                 }));
         }
 
+        // edit: left click carves a sphere where the camera is looking, right click places one
+        let edit = if input.mouse_just_pressed(MouseButton::Left) {
+            Some(0)
+        } else if input.mouse_just_pressed(MouseButton::Right) {
+            Some(1)
+        } else {
+            None
+        };
+        if let (Some(val), true) = (edit, self.grabbed_cursor) {
+            let mut chunks = self.world.query::<(&ChunkPos, &ChunkData)>();
+            let loaded: HashMap<_, _> = chunks.iter(&self.world).collect();
+            let hit = chunk::raycast(
+                state.camera.pos,
+                *state.camera.forward(),
+                256.0,
+                |pos| loaded.get(&pos).copied(),
+                chunk::is_opaque,
+            );
+            if let Some((chunk_pos, hit)) = hit {
+                let voxel = chunk_pos.voxel(LocalVoxelPos(hit.pos));
+                self.server
+                    .send(ServerMessage::EditVoxels(vec![VoxelEdit::Shape {
+                        shape: Shape::Sphere {
+                            center: voxel.cast::<f32>() + Vector3::from_element(0.5),
+                            radius: 8.0,
+                        },
+                        val,
+                    }]));
+            }
+        }
+
+        // debug: stats of the chunk the camera is in and of the renderer's chunk store
+        if input.just_pressed(Key::KeyI) {
+            let chunk_pos = ChunkPos::containing(state.camera.pos);
+            let mut chunks = self.world.query::<(&ChunkPos, &ChunkData)>();
+            match chunks.iter(&self.world).find(|(pos, _)| **pos == chunk_pos) {
+                Some((_, data)) => println!("chunk {:?}: {}", *chunk_pos, data.stats()),
+                None => println!("chunk {:?} is not loaded", *chunk_pos),
+            }
+            self.render_commands
+                .push(super::render::RenderCommand::PrintStats);
+        }
+
+        // view distance, clamped the same way the server does
+        let view_distance = state.view_distance;
+        if input.just_pressed(Key::Equal) {
+            state.view_distance = ViewDistance(state.view_distance.0 + 1).clamped();
+        }
+        if input.just_pressed(Key::Minus) {
+            state.view_distance = ViewDistance(state.view_distance.saturating_sub(1)).clamped();
+        }
+        if state.view_distance != view_distance {
+            println!("view distance: {}", *state.view_distance);
+            self.server
+                .send(ServerMessage::ViewDistance(state.view_distance));
+        }
+
+        if input.just_pressed(Key::F5) {
+            self.server.send(ServerMessage::SaveWorld);
+        }
+
         if input.just_pressed(Key::KeyR) {
             self.renderer.update_shader();
         }
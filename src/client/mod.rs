@@ -13,12 +13,12 @@ pub use app::*;
 use bevy_ecs::{entity::Entity, system::SystemId, world::World};
 use component::RenderCommands;
 use render::RenderCommand;
-use rsc::FRAME_TIME;
+use rsc::{DEFAULT_PLAYER_NAME, FRAME_TIME, MOVE_TIME, PLAYER_NAME_VAR};
 pub use state::*;
 use system::render::add_grid;
 
 use crate::{
-    common::{ClientMessage, ServerHandle, ServerMessage},
+    common::{component::ChunkData, ClientMessage, ServerHandle, ServerMessage},
     server::Server, util::timer::Timer,
 };
 
@@ -50,6 +50,8 @@ pub struct Client<'a> {
     frame_target: Instant,
     frame_time: Duration,
     second_target: Instant,
+    move_target: Instant,
+    camera_moved: bool,
     the_thing: bool,
 }
 
@@ -73,7 +75,10 @@ impl Client<'_> {
 
         let state = ClientState::new();
         let server = ServerHandle::spawn(Server::start);
-        server.send(ServerMessage::Join);
+        let name =
+            std::env::var(PLAYER_NAME_VAR).unwrap_or_else(|_| DEFAULT_PLAYER_NAME.to_owned());
+        server.send(ServerMessage::Join(name));
+        server.send(ServerMessage::ViewDistance(state.view_distance));
 
         Self {
             window,
@@ -96,6 +101,8 @@ impl Client<'_> {
             frame_target: Instant::now(),
             frame_time: FRAME_TIME,
             second_target: Instant::now(),
+            move_target: Instant::now(),
+            camera_moved: false,
             the_thing: false,
         }
     }
@@ -108,6 +115,15 @@ impl Client<'_> {
         self.handle_input(&dt);
         self.input.end();
 
+        if self.camera_moved && now >= self.move_target {
+            self.move_target = now + MOVE_TIME;
+            self.camera_moved = false;
+            self.server.send(ServerMessage::PlayerMove(
+                self.state.camera.pos.into(),
+                self.state.camera.orientation.into(),
+            ));
+        }
+
         self.recv();
         self.world
             .run_system(self.systems.render_add_grid)
@@ -160,15 +176,46 @@ impl Client<'_> {
                     let id = self.world.spawn(grid).id();
                     self.server_id_map.insert(entity, id);
                 }
+                ClientMessage::DespawnVoxelGrid(entity) => {
+                    if let Some(id) = self.server_id_map.remove(&entity) {
+                        self.world.despawn(id);
+                    }
+                }
                 ClientMessage::LoadChunk(entity, chunk) => {
                     let id = self.world.spawn(chunk).id();
                     self.server_id_map.insert(entity, id);
                 }
+                ClientMessage::UnloadChunk(entity) => {
+                    if let Some(id) = self.server_id_map.remove(&entity) {
+                        self.world.despawn(id);
+                        // queued behind the AddChunk for it, which goes through the world
+                        self.world
+                            .resource_mut::<RenderCommands>()
+                            .0
+                            .push(RenderCommand::RemoveChunk(id));
+                    }
+                }
+                ClientMessage::ChunkEdit(entity, diff) => {
+                    let Some(id) = self.server_id_map.get(&entity) else {
+                        continue;
+                    };
+                    let mut entity = self.world.entity_mut(*id);
+                    if let Some(mut data) = entity.get_mut::<ChunkData>() {
+                        // the change gets picked up by the add_chunk system
+                        *data = ChunkData::from_tree(data.apply(&diff));
+                    }
+                }
                 ClientMessage::PosUpdate(e, pos) => {
                     if let Some(id) = self.server_id_map.get(&e) {
                         self.world.entity_mut(*id).insert(pos);
                     }
                 }
+                ClientMessage::MovePlayer(pos, orientation) => {
+                    self.state.camera.pos = *pos;
+                    self.state.camera.orientation = *orientation;
+                    self.render_commands
+                        .push(RenderCommand::ViewUpdate(self.state.camera));
+                }
             }
         }
     }
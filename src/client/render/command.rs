@@ -12,8 +12,10 @@ use ndarray::Array3;
 pub enum RenderCommand {
     CreateVoxelGrid(CreateVoxelGrid),
     AddChunk(AddChunk),
+    RemoveChunk(Entity),
     UpdateGridTransform(UpdateGridTransform),
     ViewUpdate(Camera),
+    PrintStats,
 }
 
 #[derive(Debug, Clone)]
@@ -67,6 +69,21 @@ impl<'a> Renderer<'a> {
                     &mut self.staging_belt,
                     desc,
                 ),
+                RenderCommand::RemoveChunk(id) => self.voxel_pipeline.remove_chunk(
+                    &self.device,
+                    &mut self.encoder,
+                    &mut self.staging_belt,
+                    id,
+                ),
+                RenderCommand::PrintStats => {
+                    let mem = self.voxel_pipeline.store_memory();
+                    println!(
+                        "chunk store: {} chunks in {} bytes, {} bytes saved",
+                        mem.trees,
+                        mem.shared_bytes,
+                        mem.saved_bytes()
+                    );
+                }
             }
         }
         if new_camera {
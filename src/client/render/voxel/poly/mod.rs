@@ -256,7 +256,7 @@ impl VoxelPipeline {
         }
 
         let proj = Transform3::identity()
-            * Translation3::from(pos.cast() * crate::common::component::chunk::SIDE_LENGTH as f32)
+            * Translation3::from(pos.origin().cast())
             * Translation3::from(-chunk::DIMENSIONS.cast() / 2.0);
 
         for (face, meshes) in mesh.faces.iter().enumerate() {
@@ -20,7 +20,7 @@ use bevy_ecs::entity::Entity;
 pub use color::*;
 use layout::Layout;
 use nalgebra::{Projective3, Transform3, Translation3, Vector2, Vector3};
-use std::{collections::HashMap, ops::Deref};
+use std::collections::HashMap;
 use wgpu::include_wgsl;
 use {group::VoxelGroup, view::View};
 
@@ -120,7 +120,7 @@ impl VoxelPipeline {
             .update(device, encoder, belt, size, &updates);
 
         let proj = Projective3::identity()
-            * Translation3::from((pos.deref() * chunk::SIDE_LENGTH as i32).cast())
+            * Translation3::from(pos.origin().cast())
             * Translation3::from(-chunk::DIMENSIONS.cast() / 2.0);
         let group = VoxelGroup {
             transform: proj,
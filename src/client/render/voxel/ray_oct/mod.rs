@@ -6,9 +6,13 @@ mod light;
 mod view;
 
 use super::super::UpdateGridTransform;
-use crate::client::{
-    camera::Camera,
-    render::{AddChunk, CreateVoxelGrid},
+use crate::{
+    client::{
+        camera::Camera,
+        render::{util::ArrBufUpdate, AddChunk, CreateVoxelGrid},
+    },
+    common::component::ChunkPos,
+    util::oct_tree::{OctStore, StoreMemory, StoredTree},
 };
 use bevy_ecs::entity::Entity;
 pub use color::*;
@@ -25,6 +29,12 @@ pub struct VoxelPipeline {
     render_pipeline: wgpu::RenderPipeline,
     render_bind_group: wgpu::BindGroup,
     id_map: HashMap<Entity, (usize, Chunk)>,
+    positions: HashMap<Entity, ChunkPos>,
+    store: OctStore,
+    stored: HashMap<Entity, StoredTree>,
+    // the shader only draws chunks[0], so the chunk the camera is in gets kept there
+    // and the view is relative to that chunk
+    camera: Camera,
 }
 
 const RENDER_SHADER: wgpu::ShaderModuleDescriptor<'_> = include_wgsl!("shader/render.wgsl");
@@ -51,6 +61,10 @@ impl VoxelPipeline {
             render_pipeline,
             render_bind_group,
             id_map: HashMap::new(),
+            positions: HashMap::new(),
+            store: OctStore::new(),
+            stored: HashMap::new(),
+            camera: Camera::default(),
         }
     }
 
@@ -105,19 +119,99 @@ impl VoxelPipeline {
         belt: &mut wgpu::util::StagingBelt,
         AddChunk { id, pos, tree, .. }: AddChunk,
     ) {
-        let offset = self.layout.voxel_data.len() as u32;
-        self.layout
-            .voxel_data
-            .add(device, encoder, belt, tree.raw());
-
-        let chunk = Chunk { offset };
-        let i = self.layout.chunks.len();
-        self.layout.chunks.add(device, encoder, belt, &[chunk]);
+        // only the nodes the store didn't already have need to be uploaded
+        let start = self.store.raw().len();
+        let stored = self.store.insert(&tree);
+        let new = &self.store.raw()[start..];
+        if !new.is_empty() {
+            self.layout.voxel_data.add(device, encoder, belt, new);
+        }
+        let chunk = Chunk {
+            offset: stored.root.node_data(),
+        };
+        // adding a chunk that's already there replaces its tree, eg. after an edit
+        if let Some(old) = self.stored.insert(id, stored) {
+            self.store.remove(old);
+        }
+        if let Some((i, old)) = self.id_map.get_mut(&id) {
+            *old = chunk;
+            self.layout.chunks.set(device, encoder, belt, *i, &[chunk]);
+        } else {
+            let i = self.layout.chunks.len();
+            self.layout.chunks.add(device, encoder, belt, &[chunk]);
+            self.id_map.insert(id, (i, chunk));
+        }
+        self.positions.insert(id, pos);
+        if self.store.wasted_bytes() > self.store.memory().shared_bytes {
+            self.compact_store(device, encoder, belt);
+        }
+        self.pin_camera_chunk(device, encoder, belt);
+        self.compute_bind_group = self.layout.compute_bind_group(device);
+    }
 
-        self.id_map.insert(id, (i, chunk));
+    pub fn remove_chunk(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut wgpu::util::StagingBelt,
+        id: Entity,
+    ) {
+        let Some((i, _)) = self.id_map.remove(&id) else {
+            return;
+        };
+        self.positions.remove(&id);
+        if let Some(stored) = self.stored.remove(&id) {
+            self.store.remove(stored);
+        }
+        // the last chunk takes over the freed slot
+        let last = self.layout.chunks.len() - 1;
+        if i != last {
+            if let Some((j, chunk)) = self.id_map.values_mut().find(|(j, _)| *j == last) {
+                *j = i;
+                self.layout.chunks.set(device, encoder, belt, i, &[*chunk]);
+            }
+        }
+        self.layout.chunks.update(device, encoder, belt, last, &[]);
+        if self.store.wasted_bytes() > self.store.memory().shared_bytes {
+            self.compact_store(device, encoder, belt);
+        }
+        self.pin_camera_chunk(device, encoder, belt);
         self.compute_bind_group = self.layout.compute_bind_group(device);
     }
 
+    // moves every chunk's nodes, so the whole node buffer and all chunk offsets get rewritten
+    fn compact_store(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut wgpu::util::StagingBelt,
+    ) {
+        self.store.compact(self.stored.values_mut());
+        let data = self.store.raw();
+        self.layout.voxel_data.update(
+            device,
+            encoder,
+            belt,
+            data.len(),
+            &[ArrBufUpdate { offset: 0, data }],
+        );
+        for (id, (i, chunk)) in &mut self.id_map {
+            chunk.offset = self.stored[id].root.node_data();
+            self.layout.chunks.set(device, encoder, belt, *i, &[*chunk]);
+        }
+    }
+
+    fn in_slot(&self, i: usize) -> Option<Entity> {
+        self.id_map
+            .iter()
+            .find(|(_, (j, _))| *j == i)
+            .map(|(id, _)| *id)
+    }
+
+    pub fn store_memory(&self) -> StoreMemory {
+        self.store.memory()
+    }
+
     pub fn resize(&mut self, device: &wgpu::Device, size: Vector2<u32>) {
         self.layout.texture.resize(
             device,
@@ -150,10 +244,46 @@ impl VoxelPipeline {
         belt: &mut wgpu::util::StagingBelt,
         camera: &Camera,
     ) {
-        let transform =
-            Transform3::identity() * Translation3::from(camera.pos) * camera.orientation;
+        self.camera = *camera;
+        self.pin_camera_chunk(device, encoder, belt);
+    }
+
+    // swaps the chunk the camera is in into slot 0 and uploads the view in that chunk's
+    // space; if it isn't loaded whatever is in slot 0 still gets drawn where it belongs
+    fn pin_camera_chunk(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut wgpu::util::StagingBelt,
+    ) {
+        let target = ChunkPos::containing(self.camera.pos);
+        let pinned = self.in_slot(0);
+        let wanted = self
+            .positions
+            .iter()
+            .find(|(_, pos)| **pos == target)
+            .map(|(id, _)| *id);
+        if let (Some(pinned), Some(wanted)) = (pinned, wanted) {
+            if pinned != wanted {
+                let i = self.id_map[&wanted].0;
+                for (id, slot) in [(pinned, i), (wanted, 0)] {
+                    let (j, chunk) = self.id_map.get_mut(&id).expect("chunk in id_map");
+                    *j = slot;
+                    self.layout
+                        .chunks
+                        .set(device, encoder, belt, slot, &[*chunk]);
+                }
+            }
+        }
+        let chunk_pos = self
+            .in_slot(0)
+            .and_then(|id| self.positions.get(&id).copied())
+            .unwrap_or(target);
+        let transform = Transform3::identity()
+            * Translation3::from(chunk_pos.to_local(self.camera.pos))
+            * self.camera.orientation;
         let data = View {
-            zoom: camera.scale,
+            zoom: self.camera.scale,
             transform,
             ..Default::default()
         };
@@ -39,8 +39,8 @@ fn main(@builtin(global_invocation_id) cell: vec3<u32>) {
     let pixel_pos = vec2<f32>(
         (vec2<f32>(cell.xy) / view_dim_f - vec2<f32>(0.5)) * vec2<f32>(2.0, -2.0 * aspect)
     );
-    let offset = vec3<f32>(f32(1u << (view.chunk_scale - 1)));
-    let pos = view.transform * vec4<f32>(pixel_pos, 1.0, 1.0) + vec4<f32>(offset, 0.0);
+    // the view is in the space of chunks[0], its tree goes from 0 to side_len
+    let pos = view.transform * vec4<f32>(pixel_pos, 1.0, 1.0);
     let dir = view.transform * vec4<f32>(normalize(vec3<f32>(pixel_pos, view.zoom)), 0.0);
 
     var color = trace_full(pos, dir);
@@ -158,7 +158,9 @@ fn cast_ray(
 
     var t = t_start;
     var axis = axis_start;
-    var node_start = 0u;
+    // chunks point at their root in the shared node store, and every node in
+    // voxel_data is an absolute index into it
+    var node_start = data_offset;
     var scale = MAX_SCALE;
     var scale_exp2 = 1.0;
     var parents = array<u32, MAX_SCALE>();
@@ -171,7 +173,7 @@ fn cast_ray(
         if iters == MAX_ITERS { break; }
         iters += 1;
         let t_corner = vox_pos * inc_t + corner_adj;
-        let node = voxel_data[data_offset + node_start + (child ^ inv_dir_bits)];
+        let node = voxel_data[node_start + (child ^ inv_dir_bits)];
         if node >= LEAF_BIT {
             // ignore consecutive identical leaves
             if node != prev {
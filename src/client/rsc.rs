@@ -2,6 +2,11 @@ use std::time::Duration;
 
 pub const FPS: u32 = 60;
 pub const FRAME_TIME: Duration = Duration::from_millis(1000 / FPS as u64);
+// how often the camera gets sent to the server while it's moving
+pub const MOVE_TIME: Duration = Duration::from_millis(50);
+// the name the player joins with, the server keeps saved positions under it
+pub const DEFAULT_PLAYER_NAME: &str = "player";
+pub const PLAYER_NAME_VAR: &str = "VOXELGAME_PLAYER";
 
 pub const CLEAR_COLOR: wgpu::Color = wgpu::Color {
     r: 0.5,
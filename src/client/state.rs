@@ -1,9 +1,12 @@
+use crate::common::component::ViewDistance;
+
 use super::camera::Camera;
 
 pub struct ClientState {
     pub camera: Camera,
     pub camera_scroll: f32,
     pub speed: f32,
+    pub view_distance: ViewDistance,
 }
 
 impl ClientState {
@@ -12,6 +15,7 @@ impl ClientState {
             camera: Camera::default(),
             camera_scroll: 0.0,
             speed: 0.0,
+            view_distance: ViewDistance::default(),
         }
     }
 }
@@ -57,7 +57,7 @@ pub fn update_transform(
 }
 
 pub fn add_chunk(
-    query: Query<(Entity, &ChunkPos, &ChunkMesh, &ChunkData), Or<(Added<ChunkPos>, Added<ChunkMesh>, Added<ChunkData>)>>,
+    query: Query<(Entity, &ChunkPos, &ChunkMesh, &ChunkData), Or<(Added<ChunkPos>, Added<ChunkMesh>, Changed<ChunkData>)>>,
     mut renderer: ResMut<RenderCommands>,
 ) {
     for (id, pos, mesh, data) in query.iter() {
@@ -3,7 +3,7 @@ pub use mesh::*;
 
 use std::collections::{HashMap, HashSet};
 
-use crate::util::oct_tree::OctTree;
+use crate::util::oct_tree::{Compression, DecodeError, OctTree, RayHit};
 use bevy_derive::{Deref, DerefMut};
 use bevy_ecs::{bundle::Bundle, component::Component, entity::Entity, system::Resource};
 use nalgebra::Vector3;
@@ -29,6 +29,90 @@ impl ChunkData {
             data: OctTree::from_leaf(0, SCALE),
         }
     }
+    pub fn encode(&self) -> Vec<u8> {
+        self.data.encode(Compression::Rle)
+    }
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        OctTree::decode_levels(bytes, SCALE).map(Self::from_tree)
+    }
+}
+
+// false for the ids compute.wgsl in ray_oct draws see-through (its min_alpha), so rays
+// aimed at the world stop at the same voxel the player sees
+pub fn is_opaque(id: u32) -> bool {
+    !matches!(id, 0 | 3)
+}
+
+// casts a ray in world space through the chunks it passes until one of them has a voxel
+// that stops it; chunks that chunk gives None for count as empty
+pub fn raycast<'a>(
+    origin: Vector3<f32>,
+    dir: Vector3<f32>,
+    max_t: f32,
+    chunk: impl Fn(ChunkPos) -> Option<&'a ChunkData>,
+    stop: impl Fn(u32) -> bool,
+) -> Option<(ChunkPos, RayHit)> {
+    if dir == Vector3::zeros() || dir.iter().any(|d| !d.is_finite()) {
+        return None;
+    }
+    let mut pos = ChunkPos::containing(origin);
+    loop {
+        let local = pos.to_local(origin);
+        if let Some(hit) = chunk(pos).and_then(|data| data.raycast(local, dir, max_t, &stop)) {
+            return Some((pos, hit));
+        }
+        // on to the chunk behind the face the ray leaves through
+        let (axis, t) = (0..3)
+            .filter(|i| dir[*i] != 0.0)
+            .map(|i| {
+                let face = if dir[i] > 0.0 {
+                    SIDE_LENGTH as f32
+                } else {
+                    0.0
+                };
+                (i, (face - local[i]) / dir[i])
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+        if t > max_t {
+            return None;
+        }
+        pos[axis] += if dir[axis] > 0.0 { 1 } else { -1 };
+    }
+}
+
+// Voxel coordinates come in three kinds:
+//   WorldVoxelPos - any voxel in the world, i64 so every voxel of every ChunkPos fits
+//   ChunkPos - a chunk, the world voxels in [pos * SIDE_LENGTH, (pos + 1) * SIDE_LENGTH)
+//   LocalVoxelPos - a voxel within a chunk, every axis in 0..SIDE_LENGTH
+// world to chunk rounds towards negative infinity, so voxel -1 is in chunk -1 at local
+// SIDE_LENGTH - 1 and not in chunk 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deref, DerefMut)]
+pub struct WorldVoxelPos(pub Vector3<i64>);
+impl WorldVoxelPos {
+    pub fn new(x: i64, y: i64, z: i64) -> Self {
+        Self(Vector3::new(x, y, z))
+    }
+    // the voxel a point in world space is in
+    pub fn containing(pos: Vector3<f32>) -> Self {
+        Self(pos.map(|c| c.floor() as i64))
+    }
+    pub fn from_parts(chunk: ChunkPos, local: LocalVoxelPos) -> Self {
+        Self(chunk.origin().0 + local.cast::<i64>())
+    }
+    pub fn chunk(&self) -> ChunkPos {
+        ChunkPos(self.map(|c| c.div_euclid(SIDE_LENGTH as i64) as i32))
+    }
+    pub fn local(&self) -> LocalVoxelPos {
+        LocalVoxelPos(self.map(|c| c.rem_euclid(SIDE_LENGTH as i64) as usize))
+    }
+    pub fn split(&self) -> (ChunkPos, LocalVoxelPos) {
+        (self.chunk(), self.local())
+    }
+}
+impl From<Vector3<i64>> for WorldVoxelPos {
+    fn from(val: Vector3<i64>) -> Self {
+        WorldVoxelPos(val)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component, Default, Deref, DerefMut)]
@@ -37,6 +121,29 @@ impl ChunkPos {
     pub fn new(x: i32, y: i32, z: i32) -> Self {
         Self(Vector3::new(x, y, z))
     }
+    // the chunk a point in world space is in
+    pub fn containing(pos: Vector3<f32>) -> Self {
+        WorldVoxelPos::containing(pos).chunk()
+    }
+    // the world position of local voxel 0, 0, 0
+    pub fn origin(&self) -> WorldVoxelPos {
+        WorldVoxelPos(self.cast::<i64>() * SIDE_LENGTH as i64)
+    }
+    // a point in world space relative to this chunk's origin, which is the space its tree
+    // is in; the renderer and raycasts into the chunk both go through this
+    pub fn to_local(self, pos: Vector3<f32>) -> Vector3<f32> {
+        pos - self.origin().cast::<f32>()
+    }
+    pub fn voxel(&self, local: LocalVoxelPos) -> WorldVoxelPos {
+        WorldVoxelPos::from_parts(*self, local)
+    }
+    // every chunk that overlaps the box from min to max, both inclusive
+    pub fn range(min: WorldVoxelPos, max: WorldVoxelPos) -> impl Iterator<Item = ChunkPos> {
+        let (min, max) = (min.chunk(), max.chunk());
+        (min.x..=max.x).flat_map(move |x| {
+            (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| Self::new(x, y, z)))
+        })
+    }
 }
 impl From<Vector3<i32>> for ChunkPos {
     fn from(val: Vector3<i32>) -> Self {
@@ -44,6 +151,9 @@ impl From<Vector3<i32>> for ChunkPos {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deref, DerefMut)]
+pub struct LocalVoxelPos(pub Vector3<usize>);
+
 #[derive(Debug, Clone, Component, Deref, DerefMut)]
 pub struct LoadedChunks {
     loaded: HashSet<ChunkPos>,
@@ -79,3 +189,126 @@ pub struct ChunkBundle {
     pub data: ChunkData,
     pub mesh: ChunkMesh,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use nalgebra::Vector3;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::util::oct_tree::OctTreeBuilder;
+
+    const S: i64 = SIDE_LENGTH as i64;
+
+    #[test]
+    fn negative_coordinates() {
+        let cases = [
+            (0, 0, 0),
+            (S - 1, 0, S as usize - 1),
+            (S, 1, 0),
+            (-1, -1, S as usize - 1),
+            (-S, -1, 0),
+            (-S - 1, -2, S as usize - 1),
+            (-2 * S, -2, 0),
+        ];
+        for (world, chunk, local) in cases {
+            let pos = WorldVoxelPos::new(world, world, world);
+            assert_eq!(pos.chunk(), ChunkPos::new(chunk, chunk, chunk), "{world}");
+            assert_eq!(*pos.local(), Vector3::from_element(local), "{world}");
+        }
+        assert_eq!(
+            ChunkPos::containing(Vector3::new(-0.5, 0.5, S as f32 - 0.5)),
+            ChunkPos::new(-1, 0, 0)
+        );
+        assert_eq!(
+            WorldVoxelPos::containing(Vector3::new(-0.5, -1.0, 2.7)),
+            WorldVoxelPos::new(-1, -1, 2)
+        );
+        assert_eq!(
+            ChunkPos::new(-1, 0, 2).origin(),
+            WorldVoxelPos::new(-S, 0, 2 * S)
+        );
+    }
+
+    #[test]
+    fn split_round_trip() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..10_000 {
+            let range = [S, 4 * S, i32::MAX as i64 * S][rng.gen_range(0..3)];
+            let pos = WorldVoxelPos(Vector3::from_fn(|_, _| rng.gen_range(-range..range)));
+            let (chunk, local) = pos.split();
+            assert!(local.iter().all(|c| *c < SIDE_LENGTH));
+            assert_eq!(WorldVoxelPos::from_parts(chunk, local), pos);
+            assert_eq!(chunk.voxel(local), pos);
+        }
+    }
+
+    #[test]
+    fn raycast_across_chunks() {
+        let chunk = |pos: Vec<Vector3<usize>>| {
+            let mut builder = OctTreeBuilder::from_leaf(0, SCALE);
+            for p in pos {
+                builder.set(p, 1);
+            }
+            ChunkData::from_tree(builder.build())
+        };
+        let s = SIDE_LENGTH;
+        let chunks = HashMap::from([
+            (ChunkPos::new(0, 0, 0), chunk(vec![])),
+            (
+                ChunkPos::new(-1, 0, 0),
+                chunk(vec![Vector3::new(s - 3, 5, 5)]),
+            ),
+            (ChunkPos::new(0, 0, 1), chunk(vec![Vector3::new(2, 5, 0)])),
+        ]);
+        let get = |pos: ChunkPos| chunks.get(&pos);
+        let solid = |id: u32| id != 0;
+
+        // from chunk 0 into chunk -1, the hit is reported in the chunk it's in
+        let origin = Vector3::new(10.5, 5.5, 5.5);
+        let (pos, hit) = raycast(origin, -Vector3::x(), 100.0, get, solid).unwrap();
+        assert_eq!(pos, ChunkPos::new(-1, 0, 0));
+        assert_eq!(hit.pos, Vector3::new(s - 3, 5, 5));
+        assert_eq!(
+            pos.voxel(LocalVoxelPos(hit.pos)),
+            WorldVoxelPos::new(-3, 5, 5)
+        );
+        assert_eq!(hit.t, 12.5);
+        assert_eq!(hit.normal, Vector3::new(1, 0, 0));
+        assert_eq!(raycast(origin, -Vector3::x(), 12.0, get, solid), None);
+
+        // crossing z, starting right at the border
+        let origin = Vector3::new(2.5, 5.5, S as f32 - 0.5);
+        let (pos, hit) = raycast(origin, Vector3::z(), 100.0, get, solid).unwrap();
+        assert_eq!(pos, ChunkPos::new(0, 0, 1));
+        assert_eq!(hit.pos, Vector3::new(2, 5, 0));
+        assert_eq!(hit.t, 0.5);
+
+        // chunks that aren't loaded are skipped, not walls
+        let origin = Vector3::new(-S as f32 - 10.5, 5.5, 5.5);
+        let (pos, hit) = raycast(origin, Vector3::x(), 1000.0, get, solid).unwrap();
+        assert_eq!(pos, ChunkPos::new(-1, 0, 0));
+        assert_eq!(hit.t, S as f32 + 7.5);
+        assert_eq!(raycast(origin, Vector3::y(), 10_000.0, get, solid), None);
+    }
+
+    #[test]
+    fn range() {
+        let w = WorldVoxelPos::new;
+        let count = |min, max| ChunkPos::range(min, max).count();
+        assert_eq!(
+            ChunkPos::range(w(5, 5, 5), w(5, 5, 5)).collect::<Vec<_>>(),
+            vec![ChunkPos::new(0, 0, 0)]
+        );
+        // a box over the corner of chunk 0 touches all 8 chunks around it
+        let corner: HashSet<_> = ChunkPos::range(w(-1, -1, -1), w(0, 0, 0)).collect();
+        assert_eq!(corner.len(), 8);
+        assert!(corner.iter().all(|c| c.iter().all(|c| *c == 0 || *c == -1)));
+        // max is inclusive, a box that ends on the last voxel of a chunk stays in it
+        assert_eq!(count(w(0, 0, 0), w(S - 1, 0, 0)), 1);
+        assert_eq!(count(w(0, 0, 0), w(S, 0, 0)), 2);
+        assert_eq!(count(w(-S - 1, 0, -1), w(S, S, 0)), 4 * 2 * 2);
+    }
+}
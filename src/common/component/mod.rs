@@ -2,7 +2,9 @@ pub mod chunk;
 mod grid;
 
 use chunk::LoadedChunks;
-pub use chunk::{ChunkBundle, ChunkData, ChunkMap, ChunkMesh, ChunkPos};
+pub use chunk::{
+    ChunkBundle, ChunkData, ChunkMap, ChunkMesh, ChunkPos, LocalVoxelPos, WorldVoxelPos,
+};
 pub use grid::*;
 
 use bevy_derive::{Deref, DerefMut};
@@ -39,22 +41,43 @@ impl From<Rotation3<f32>> for Orientation {
     }
 }
 
-#[derive(Debug, Clone, Copy, Component)]
-pub struct Player;
+// the name a player joined with, which is what their saved position is kept under
+#[derive(Debug, Clone, Component)]
+pub struct Player {
+    pub name: String,
+}
+
+// how many chunks around a player get loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Deref, DerefMut)]
+pub struct ViewDistance(pub u32);
+impl ViewDistance {
+    pub const MAX: u32 = 12;
+    // what the server actually loads for a requested distance
+    pub fn clamped(self) -> Self {
+        Self(self.0.clamp(1, Self::MAX))
+    }
+}
+impl Default for ViewDistance {
+    fn default() -> Self {
+        Self(4)
+    }
+}
 
 #[derive(Debug, Clone, Bundle)]
 pub struct PlayerBundle {
     pub player: Player,
     pub loaded_chunks: LoadedChunks,
+    pub view_distance: ViewDistance,
     pub pos: Pos,
     pub orientation: Orientation,
 }
 
 impl PlayerBundle {
-    pub fn new() -> Self {
+    pub fn new(name: String) -> Self {
         Self {
-            player: Player,
+            player: Player { name },
             loaded_chunks: LoadedChunks::new(),
+            view_distance: ViewDistance::default(),
             pos: Pos::default(),
             orientation: Orientation::default(),
         }
@@ -0,0 +1,75 @@
+use nalgebra::Vector3;
+
+use crate::{
+    common::component::{chunk::SIDE_LENGTH, ChunkPos, WorldVoxelPos},
+    util::oct_tree::{CsgOp, OctTree, Shape},
+};
+
+#[derive(Debug, Clone)]
+pub enum VoxelEdit {
+    Set {
+        pos: WorldVoxelPos,
+        val: u32,
+    },
+    // min and max are both inclusive
+    FillBox {
+        min: WorldVoxelPos,
+        max: WorldVoxelPos,
+        val: u32,
+    },
+    // every voxel with its center in the shape becomes val, the shape is in world voxels
+    Shape {
+        shape: Shape,
+        val: u32,
+    },
+}
+
+impl VoxelEdit {
+    // the voxels the edit can change, both inclusive; None if that's unbounded
+    pub fn bounds(&self) -> Option<(WorldVoxelPos, WorldVoxelPos)> {
+        match self {
+            VoxelEdit::Set { pos, .. } => Some((*pos, *pos)),
+            VoxelEdit::FillBox { min, max, .. } => {
+                Some((WorldVoxelPos(min.inf(max)), WorldVoxelPos(min.sup(max))))
+            }
+            VoxelEdit::Shape { shape, .. } => shape.bounds().map(|(min, max)| {
+                (
+                    WorldVoxelPos::containing(min),
+                    WorldVoxelPos::containing(max),
+                )
+            }),
+        }
+    }
+    // the part of the edit that's in chunk applied to its tree
+    pub fn apply(&self, chunk: ChunkPos, tree: &OctTree) -> OctTree {
+        let origin = chunk.origin();
+        match self {
+            VoxelEdit::Set { pos, val } => Self::fill_box(origin, tree, (*pos, *pos), *val),
+            VoxelEdit::FillBox { val, .. } => {
+                Self::fill_box(origin, tree, self.bounds().unwrap(), *val)
+            }
+            VoxelEdit::Shape { shape, val } => {
+                let shape = shape.translated(-origin.cast::<f32>());
+                if *val == 0 {
+                    tree.with_shape(&shape, 1, CsgOp::Subtract)
+                } else {
+                    tree.with_shape(&shape, *val, CsgOp::Union)
+                }
+            }
+        }
+    }
+    fn fill_box(
+        origin: WorldVoxelPos,
+        tree: &OctTree,
+        (min, max): (WorldVoxelPos, WorldVoxelPos),
+        val: u32,
+    ) -> OctTree {
+        let side = SIDE_LENGTH as i64;
+        let min = (*min - *origin).map(|c| c.clamp(0, side) as usize);
+        let max = (*max - *origin + Vector3::from_element(1)).map(|c| c.clamp(0, side) as usize);
+        // builders keep untouched groups where they are, which keeps diffing them cheap
+        let mut builder = tree.to_builder();
+        builder.set_region(min, max, val);
+        builder.build()
+    }
+}
@@ -1,14 +1,26 @@
 use crate::{
-    common::component::{ChunkBundle, Pos, VoxelGridBundle},
-    util::thread::{ExitType, ThreadChannel, ThreadHandle},
+    common::{
+        component::{ChunkBundle, Orientation, Pos, ViewDistance, VoxelGridBundle},
+        VoxelEdit,
+    },
+    util::{
+        oct_tree::OctDiff,
+        thread::{ExitType, ThreadChannel, ThreadHandle},
+    },
 };
 use bevy_ecs::entity::Entity;
 
 #[derive(Clone)]
 pub enum ServerMessage {
     Stop,
-    Join,
+    // the player's name, a player that rejoins with the same name gets their saved position back
+    Join(String),
     SpawnVoxelGrid(VoxelGridBundle),
+    PlayerMove(Pos, Orientation),
+    ViewDistance(ViewDistance),
+    // applied in order, edits out of reach or touching chunks that aren't loaded are dropped
+    EditVoxels(Vec<VoxelEdit>),
+    SaveWorld,
 }
 
 impl ExitType for ServerMessage {
@@ -20,8 +32,13 @@ impl ExitType for ServerMessage {
 #[derive(Clone)]
 pub enum ClientMessage {
     SpawnVoxelGrid(Entity, VoxelGridBundle),
+    DespawnVoxelGrid(Entity),
     LoadChunk(Entity, ChunkBundle),
+    UnloadChunk(Entity),
+    ChunkEdit(Entity, OctDiff),
     PosUpdate(Entity, Pos),
+    // puts the receiving player's camera somewhere else
+    MovePlayer(Pos, Orientation),
 }
 
 pub type ClientChannel = ThreadChannel<ClientMessage, ServerMessage>;
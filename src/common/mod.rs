@@ -1,4 +1,6 @@
 pub mod component;
+mod edit;
 mod message;
 
+pub use edit::*;
 pub use message::*;
@@ -1,72 +1,154 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use bevy_ecs::{entity::Entity, system::Commands};
 
 use crate::{
     common::component::{ChunkBundle, ChunkData, ChunkMesh, ChunkPos},
-    server::generation::generate_tree,
-    util::{oct_tree::OctTree, thread::{ExitType, ThreadChannel, ThreadHandle}},
+    server::{
+        generation::{generate_tree, WorldGenerator, PAR_SPLIT},
+        region::{load_chunk, region_of, save_chunks},
+    },
+    util::thread::{ExitType, ThreadChannel, ThreadHandle},
 };
 
 pub struct ChunkManager {
     handles: Vec<ThreadHandle<ChunkLoaderMsg, ServerChunkMsg>>,
-    n: usize,
     map: HashMap<ChunkPos, Entity>,
     generating: HashSet<ChunkPos>,
     available: Vec<usize>,
+    // chunks wanted this tick, lower priority gets generated first
+    requests: HashMap<ChunkPos, i32>,
+    writer: ThreadHandle<RegionWriterMsg, RegionWriterDone>,
+    // chunks handed to the writer that might not be on disk yet, including ones it failed
+    // to write and keeps retrying, so loading them from a region file could give an old version
+    unsaved: HashMap<ChunkPos, (u64, ChunkData)>,
+    saves: u64,
 }
 
 impl ChunkManager {
-    pub fn new() -> Self {
-        let n = 1;
+    pub fn new(dir: &Path, generator: Arc<dyn WorldGenerator>) -> Self {
+        let n = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_threads(dir, generator, n)
+    }
+    // chunks get saved in a directory per generator, so switching generators doesn't mix terrain
+    pub fn with_threads(dir: &Path, generator: Arc<dyn WorldGenerator>, n: usize) -> Self {
+        let n = n.max(1);
+        let dir = dir.join(generator.name());
+        let version = generator.version();
+        // with several loaders the pool already uses the cores, so each chunk is built on
+        // its loader's thread instead of starting even more threads
+        let split = if n > 1 { 0 } else { PAR_SPLIT };
         Self {
-            handles: std::iter::repeat_with(|| ThreadHandle::spawn(chunk_loader_main))
-                .take(n)
-                .collect(),
-            n,
+            handles: std::iter::repeat_with(|| {
+                let dir = dir.clone();
+                let generator = generator.clone();
+                ThreadHandle::spawn(move |ch| chunk_loader_main(ch, dir, generator, split))
+            })
+            .take(n)
+            .collect(),
             map: HashMap::new(),
             generating: HashSet::new(),
             available: (0..n).collect(),
+            requests: HashMap::new(),
+            writer: ThreadHandle::spawn(move |ch| region_writer_main(ch, dir, version)),
+            unsaved: HashMap::new(),
+            saves: 0,
         }
     }
     pub fn entity_at(&self, pos: &ChunkPos) -> Option<&Entity> {
         self.map.get(pos)
     }
-    pub fn is_generating(&self, pos: &ChunkPos) -> bool {
-        self.generating.contains(pos)
+    // has to be called every tick for as long as the chunk is wanted, eg. with the distance
+    // to the closest player; anything that isn't requested again gets cancelled in update
+    pub fn request(&mut self, pos: ChunkPos, priority: i32) {
+        if self.map.contains_key(&pos) {
+            return;
+        }
+        let p = self.requests.entry(pos).or_insert(priority);
+        *p = (*p).min(priority);
     }
-    pub fn try_load(&mut self, pos: ChunkPos) -> bool {
-        if !self.is_generating(&pos) {
-            if let Some(i) = self.available.pop() {
-                self.handles[i].send(ChunkLoaderMsg::Generate(pos));
-                self.generating.insert(pos);
-                true
-            } else {
-                false
+    // despawns every chunk that isn't in keep
+    pub fn retain(&mut self, keep: &HashSet<ChunkPos>, commands: &mut Commands) {
+        self.map.retain(|pos, id| {
+            if !keep.contains(pos) {
+                commands.entity(*id).despawn();
             }
-        } else {
-            false
-        }
+            keep.contains(pos)
+        });
+    }
+    // writes the chunk to its region file in the background, only edited chunks need
+    // this since everything else gets generated the same way again
+    pub fn save(&mut self, pos: ChunkPos, data: &ChunkData) {
+        self.saves += 1;
+        self.unsaved.insert(pos, (self.saves, data.clone()));
+        self.writer
+            .send(RegionWriterMsg::Save(pos, self.saves, data.clone()));
     }
     pub fn update(&mut self, commands: &mut Commands) {
-        self.handles.iter_mut().enumerate().for_each(|(i, h)| {
-            if let Some(msg) = h.recv().next() {
+        for RegionWriterDone::Saved(saved) in self.writer.recv() {
+            for (pos, save) in saved {
+                if self.unsaved.get(&pos).is_some_and(|(s, _)| *s == save) {
+                    self.unsaved.remove(&pos);
+                }
+            }
+        }
+        let mut done = Vec::new();
+        for (i, h) in self.handles.iter().enumerate() {
+            for msg in h.recv() {
                 self.available.push(i);
-                match msg {
-                    ServerChunkMsg::ChunkGenerated(chunk) => {
-                        let id = commands
-                            .spawn(ChunkBundle {
-                                pos: chunk.pos,
-                                data: chunk.data,
-                                mesh: chunk.mesh,
-                            })
-                            .id();
-                        self.map.insert(chunk.pos, id);
-                        self.generating.remove(&chunk.pos);
+                done.push(msg);
+            }
+        }
+        for msg in done {
+            match msg {
+                ServerChunkMsg::ChunkGenerated(chunk) | ServerChunkMsg::ChunkLoaded(chunk) => {
+                    self.generating.remove(&chunk.pos);
+                    // only spawned if somebody still wants it
+                    if self.requests.contains_key(&chunk.pos) {
+                        self.spawn(commands, chunk);
                     }
                 }
             }
-        });
+        }
+        let mut queue: BinaryHeap<_> = self
+            .requests
+            .drain()
+            .filter(|(pos, _)| !self.generating.contains(pos) && !self.map.contains_key(pos))
+            .map(|(pos, priority)| Reverse((priority, pos.x, pos.y, pos.z)))
+            .collect();
+        while let Some(Reverse((_, x, y, z))) = queue.pop() {
+            let pos = ChunkPos::new(x, y, z);
+            if let Some((_, data)) = self.unsaved.get(&pos) {
+                let chunk = GeneratedChunk {
+                    pos,
+                    data: data.clone(),
+                    mesh: ChunkMesh {},
+                };
+                self.spawn(commands, chunk);
+                continue;
+            }
+            let Some(i) = self.available.pop() else {
+                break;
+            };
+            self.handles[i].send(ChunkLoaderMsg::Generate(pos));
+            self.generating.insert(pos);
+        }
+    }
+    fn spawn(&mut self, commands: &mut Commands, chunk: GeneratedChunk) {
+        let id = commands
+            .spawn(ChunkBundle {
+                pos: chunk.pos,
+                data: chunk.data,
+                mesh: chunk.mesh,
+            })
+            .id();
+        self.map.insert(chunk.pos, id);
     }
 }
 
@@ -82,11 +164,15 @@ impl Drop for ChunkManager {
             h.send(ChunkLoaderMsg::Exit);
             h.join();
         }
+        // the writer finishes everything that was queued before exiting
+        self.writer.send(RegionWriterMsg::Exit);
+        self.writer.join();
     }
 }
 
 enum ServerChunkMsg {
     ChunkGenerated(GeneratedChunk),
+    ChunkLoaded(GeneratedChunk),
 }
 
 enum ChunkLoaderMsg {
@@ -100,23 +186,24 @@ impl ExitType for ChunkLoaderMsg {
     }
 }
 
-fn chunk_loader_main(channel: ThreadChannel<ServerChunkMsg, ChunkLoaderMsg>) {
+fn chunk_loader_main(
+    channel: ThreadChannel<ServerChunkMsg, ChunkLoaderMsg>,
+    dir: PathBuf,
+    generator: Arc<dyn WorldGenerator>,
+    split: u32,
+) {
     'outer: loop {
         match channel.recv_wait() {
             ChunkLoaderMsg::Generate(pos) => {
-                let start = std::time::Instant::now();
-                let tree = ChunkData::from_tree(generate_tree(pos));
-                let tree_time = std::time::Instant::now() - start;
-
-                // let worst = OctTree::from_fn(f_leaf, f_node, levels);
-
-                println!(
-                    "gen time: {:<5?}; size: {} nodes = {} bytes",
-                    tree_time,
-                    tree.raw().len(),
-                    std::mem::size_of_val(tree.raw())
-                );
-
+                if let Some(data) = load_chunk(&dir, pos, generator.version()) {
+                    channel.send(ServerChunkMsg::ChunkLoaded(GeneratedChunk {
+                        pos,
+                        data,
+                        mesh: ChunkMesh {},
+                    }));
+                    continue;
+                }
+                let tree = ChunkData::from_tree(generate_tree(&*generator, pos, split));
                 channel.send(ServerChunkMsg::ChunkGenerated(GeneratedChunk {
                     pos,
                     data: tree,
@@ -129,3 +216,78 @@ fn chunk_loader_main(channel: ThreadChannel<ServerChunkMsg, ChunkLoaderMsg>) {
         }
     }
 }
+
+enum RegionWriterMsg {
+    Save(ChunkPos, u64, ChunkData),
+    Exit,
+}
+
+impl ExitType for RegionWriterMsg {
+    fn exit() -> Self {
+        Self::Exit
+    }
+}
+
+enum RegionWriterDone {
+    Saved(Vec<(ChunkPos, u64)>),
+}
+
+// how long the writer waits before trying a region that failed to save again
+const RETRY_TIME: Duration = Duration::from_secs(5);
+
+// waits for chunks to save, then writes everything that queued up meanwhile
+// with one append per region; regions that fail stay queued and get retried
+fn region_writer_main(
+    channel: ThreadChannel<RegionWriterDone, RegionWriterMsg>,
+    dir: PathBuf,
+    version: u32,
+) {
+    let mut exit = false;
+    let mut regions: HashMap<_, HashMap<usize, (ChunkPos, u64, ChunkData)>> = HashMap::new();
+    while !exit {
+        let first = if regions.is_empty() {
+            Some(channel.recv_wait())
+        } else {
+            channel.recv_timeout(RETRY_TIME)
+        };
+        for msg in first.into_iter().chain(channel.recv()) {
+            match msg {
+                RegionWriterMsg::Save(pos, save, data) => {
+                    let (region, i) = region_of(pos);
+                    regions
+                        .entry(region)
+                        .or_default()
+                        .insert(i, (pos, save, data));
+                }
+                RegionWriterMsg::Exit => exit = true,
+            }
+        }
+        let mut saved = Vec::new();
+        regions.retain(|region, chunks| {
+            let encoded: Vec<_> = chunks
+                .iter()
+                .map(|(i, (_, _, data))| (*i, data.encode()))
+                .collect();
+            match save_chunks(&dir, *region, &encoded, version) {
+                Ok(()) => {
+                    saved.extend(chunks.values().map(|(pos, save, _)| (*pos, *save)));
+                    false
+                }
+                Err(err) if exit => {
+                    println!(
+                        "failed to save region {:?}, losing {} edited chunks: {}",
+                        region,
+                        chunks.len(),
+                        err
+                    );
+                    false
+                }
+                Err(err) => {
+                    println!("failed to save region {:?}, trying again later: {}", region, err);
+                    true
+                }
+            }
+        });
+        channel.send(RegionWriterDone::Saved(saved));
+    }
+}
@@ -0,0 +1,62 @@
+use bevy_ecs::{entity::Entity, query::With, world::World};
+
+use crate::{
+    common::{
+        component::{chunk::LoadedChunks, ChunkData, ChunkPos, Player, Pos},
+        ClientMessage, VoxelEdit,
+    },
+    server::{chunk::ChunkManager, rsc::MAX_REACH, ClientComponent},
+    util::oct_tree::OctTree,
+};
+
+pub fn edit_voxels(world: &mut World, player: Entity, edits: Vec<VoxelEdit>) {
+    for edit in edits {
+        if let Err(err) = edit_voxel(world, player, &edit) {
+            println!("rejected edit {:?}: {}", edit, err);
+        }
+    }
+}
+
+fn edit_voxel(world: &mut World, player: Entity, edit: &VoxelEdit) -> Result<(), &'static str> {
+    let pos = **world.get::<Pos>(player).ok_or("player hasn't joined")?;
+    if let VoxelEdit::Shape { shape, .. } = edit {
+        if !shape.is_valid() {
+            return Err("invalid shape");
+        }
+    }
+    let (min, max) = edit.bounds().ok_or("edit is unbounded")?;
+    // the corner of the edit furthest from the player has to be in reach
+    let (minf, maxf) = (min.cast::<f32>(), max.cast::<f32>().add_scalar(1.0));
+    let furthest = (minf - pos).abs().sup(&(maxf - pos).abs()).norm();
+    if furthest.is_nan() || furthest > MAX_REACH {
+        return Err("out of reach");
+    }
+    let manager = world.non_send_resource::<ChunkManager>();
+    let chunks = ChunkPos::range(min, max)
+        .map(|pos| manager.entity_at(&pos).map(|id| (pos, *id)))
+        .collect::<Option<Vec<_>>>()
+        .ok_or("chunk isn't loaded")?;
+
+    for (pos, id) in chunks {
+        let Some(old) = world.get::<ChunkData>(id) else {
+            continue;
+        };
+        let new = edit.apply(pos, old);
+        let diff = OctTree::diff(old, &new);
+        if diff.is_empty() {
+            continue;
+        }
+        let data = ChunkData::from_tree(new);
+        world.entity_mut(id).insert(data.clone());
+        world
+            .non_send_resource_mut::<ChunkManager>()
+            .save(pos, &data);
+        let mut q = world.query_filtered::<(&LoadedChunks, &mut ClientComponent), With<Player>>();
+        for (loaded, mut client) in q.iter_mut(world) {
+            if loaded.contains(&pos) {
+                client.send(ClientMessage::ChunkEdit(id, diff.clone()));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use nalgebra::Vector3;
+
+use crate::common::component::{chunk, ChunkPos};
+
+use super::NumRange;
+
+// distance in voxels between the random values caves get interpolated from
+const CELL: usize = 16;
+const CELLS: usize = chunk::SIDE_LENGTH / CELL;
+const LATTICE: usize = CELLS + 1;
+
+// 3d value noise for one chunk: random values on a lattice fixed to the world, so
+// neighbouring chunks line up, smoothly interpolated in between; interpolating never
+// leaves the range of a cell's corners, which is what makes pruning nodes possible
+pub struct CaveMap {
+    lattice: Vec<f32>,
+    // per cell the range of its 8 corners, then 2x2x2 cells merged per level
+    levels: Vec<Vec<NumRange>>,
+}
+
+impl CaveMap {
+    pub fn new(seed: u64, pos: ChunkPos) -> Self {
+        let origin = pos.origin().0 / CELL as i64;
+        let mut lattice = Vec::with_capacity(LATTICE.pow(3));
+        for z in 0..LATTICE {
+            for y in 0..LATTICE {
+                for x in 0..LATTICE {
+                    let p = origin + Vector3::new(x, y, z).cast::<i64>();
+                    lattice.push(hash(seed, p));
+                }
+            }
+        }
+        let mut first = Vec::with_capacity(CELLS.pow(3));
+        for z in 0..CELLS {
+            for y in 0..CELLS {
+                for x in 0..CELLS {
+                    let mut range = NumRange {
+                        min: f32::INFINITY,
+                        max: f32::NEG_INFINITY,
+                    };
+                    for c in 0..8 {
+                        let v =
+                            lattice[lattice_index(x + (c >> 2), y + ((c >> 1) & 1), z + (c & 1))];
+                        range.min = range.min.min(v);
+                        range.max = range.max.max(v);
+                    }
+                    first.push(range);
+                }
+            }
+        }
+        let mut levels = vec![first];
+        let mut size = CELLS;
+        while size > 1 {
+            let prev = levels.last().unwrap();
+            size /= 2;
+            let mut new = Vec::with_capacity(size.pow(3));
+            for z in 0..size {
+                for y in 0..size {
+                    for x in 0..size {
+                        let mut range = NumRange {
+                            min: f32::INFINITY,
+                            max: f32::NEG_INFINITY,
+                        };
+                        for c in 0..8 {
+                            let (cx, cy, cz) =
+                                (x * 2 + (c >> 2), y * 2 + ((c >> 1) & 1), z * 2 + (c & 1));
+                            let r = &prev[cx + cy * size * 2 + cz * size * size * 4];
+                            range.min = range.min.min(r.min);
+                            range.max = range.max.max(r.max);
+                        }
+                        new.push(range);
+                    }
+                }
+            }
+            levels.push(new);
+        }
+        Self { lattice, levels }
+    }
+
+    // the value at the center of voxel p, in 0..1
+    pub fn get(&self, p: Vector3<usize>) -> f32 {
+        let cell = p / CELL;
+        let t = p.map(|c| {
+            let t = ((c % CELL) as f32 + 0.5) / CELL as f32;
+            t * t * (3.0 - 2.0 * t)
+        });
+        let mut v = 0.0;
+        for c in 0..8 {
+            let corner = Vector3::new(c >> 2, (c >> 1) & 1, c & 1);
+            let w = (0..3)
+                .map(|i| if corner[i] == 0 { 1.0 - t[i] } else { t[i] })
+                .product::<f32>();
+            let l = cell + corner;
+            v += w * self.lattice[lattice_index(l.x, l.y, l.z)];
+        }
+        v
+    }
+
+    // bounds of every value in the node at p with side length 2^scale
+    pub fn range(&self, p: Vector3<usize>, scale: u32) -> NumRange {
+        let side_len = 2usize.pow(scale);
+        if side_len <= CELL {
+            // within a cell the value only ever grows or shrinks along each axis,
+            // so the extremes are at the corners of the node
+            let mut range = NumRange {
+                min: f32::INFINITY,
+                max: f32::NEG_INFINITY,
+            };
+            for c in 0..8 {
+                let corner = Vector3::new(c >> 2, (c >> 1) & 1, c & 1) * (side_len - 1);
+                let v = self.get(p + corner);
+                range.min = range.min.min(v);
+                range.max = range.max.max(v);
+            }
+            return range;
+        }
+        let level = (side_len / CELL).trailing_zeros() as usize;
+        let size = CELLS >> level;
+        let i = p / side_len;
+        let range = &self.levels[level][i.x + i.y * size + i.z * size * size];
+        NumRange {
+            min: range.min,
+            max: range.max,
+        }
+    }
+}
+
+fn lattice_index(x: usize, y: usize, z: usize) -> usize {
+    x + y * LATTICE + z * LATTICE * LATTICE
+}
+
+// a random value in 0..1 for each lattice point
+fn hash(seed: u64, p: Vector3<i64>) -> f32 {
+    let mut h = seed
+        ^ (p.x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (p.y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+        ^ (p.z as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
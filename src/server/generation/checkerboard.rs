@@ -0,0 +1,66 @@
+use nalgebra::Vector3;
+
+use crate::common::component::{ChunkPos, WorldVoxelPos};
+
+use super::{ChunkGenerator, WorldGenerator, MATERIALS};
+
+// a floor just below y = 0 of cubes going through every material, so neighbouring cubes
+// always differ; for checking how materials look
+#[derive(Debug, Clone)]
+pub struct CheckerboardGenerator {
+    // side length of the cubes, a power of 2 up to chunk::SIDE_LENGTH
+    pub cell: u32,
+}
+
+impl Default for CheckerboardGenerator {
+    fn default() -> Self {
+        Self { cell: 32 }
+    }
+}
+
+impl WorldGenerator for CheckerboardGenerator {
+    fn name(&self) -> &'static str {
+        "checkerboard"
+    }
+    fn version(&self) -> u32 {
+        1
+    }
+    fn uniform(&self, pos: ChunkPos) -> Option<u32> {
+        (pos.y != -1).then_some(0)
+    }
+    fn chunk(&self, pos: ChunkPos) -> Box<dyn ChunkGenerator + '_> {
+        Box::new(CheckerboardChunk {
+            cell: self.cell as i64,
+            origin: pos.origin(),
+        })
+    }
+}
+
+struct CheckerboardChunk {
+    cell: i64,
+    origin: WorldVoxelPos,
+}
+
+impl ChunkGenerator for CheckerboardChunk {
+    fn leaf(&self, p: Vector3<usize>) -> u32 {
+        let pos = *self.origin + p.cast::<i64>();
+        if pos.y < -self.cell || pos.y >= 0 {
+            return 0;
+        }
+        let cells = pos.x.div_euclid(self.cell) + pos.z.div_euclid(self.cell);
+        cells.rem_euclid(MATERIALS as i64 - 1) as u32 + 1
+    }
+    fn node(&self, p: Vector3<usize>, scale: u32) -> Option<u32> {
+        let side_len = 2i64.pow(scale);
+        let min = self.origin.y + p.y as i64;
+        let max = min + side_len - 1;
+        if max < -self.cell || min >= 0 {
+            Some(0)
+        } else if side_len <= self.cell && min >= -self.cell && max < 0 {
+            // inside a single cube
+            Some(self.leaf(p))
+        } else {
+            None
+        }
+    }
+}
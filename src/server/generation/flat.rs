@@ -0,0 +1,86 @@
+use nalgebra::Vector3;
+
+use crate::common::component::{chunk, ChunkPos};
+
+use super::{ChunkGenerator, WorldGenerator};
+
+// horizontal layers below y = 0, air above them and below everything else
+#[derive(Debug, Clone)]
+pub struct FlatGenerator {
+    // (material, thickness) from the top down
+    pub layers: Vec<(u32, u32)>,
+    pub below: u32,
+}
+
+impl Default for FlatGenerator {
+    fn default() -> Self {
+        Self {
+            layers: vec![(2, 8)],
+            below: 1,
+        }
+    }
+}
+
+impl FlatGenerator {
+    // 0 is the air above, then one band per layer, then everything below
+    fn band(&self, y: i64) -> usize {
+        if y >= 0 {
+            return 0;
+        }
+        let mut bottom = 0;
+        for (i, (_, thickness)) in self.layers.iter().enumerate() {
+            bottom -= *thickness as i64;
+            if y >= bottom {
+                return i + 1;
+            }
+        }
+        self.layers.len() + 1
+    }
+    fn material(&self, band: usize) -> u32 {
+        match band {
+            0 => 0,
+            b if b > self.layers.len() => self.below,
+            b => self.layers[b - 1].0,
+        }
+    }
+}
+
+impl WorldGenerator for FlatGenerator {
+    fn name(&self) -> &'static str {
+        "superflat"
+    }
+    fn version(&self) -> u32 {
+        1
+    }
+    fn uniform(&self, pos: ChunkPos) -> Option<u32> {
+        FlatChunk {
+            settings: self,
+            y: pos.origin().y,
+        }
+        .node(Vector3::zeros(), chunk::SCALE)
+    }
+    fn chunk(&self, pos: ChunkPos) -> Box<dyn ChunkGenerator + '_> {
+        Box::new(FlatChunk {
+            settings: self,
+            y: pos.origin().y,
+        })
+    }
+}
+
+struct FlatChunk<'a> {
+    settings: &'a FlatGenerator,
+    y: i64,
+}
+
+impl ChunkGenerator for FlatChunk<'_> {
+    fn leaf(&self, p: Vector3<usize>) -> u32 {
+        let s = self.settings;
+        s.material(s.band(self.y + p.y as i64))
+    }
+    fn node(&self, p: Vector3<usize>, scale: u32) -> Option<u32> {
+        let s = self.settings;
+        let min = self.y + p.y as i64;
+        let band = s.band(min);
+        (band == s.band(min + 2i64.pow(scale) - 1)).then(|| s.material(band))
+    }
+}
@@ -0,0 +1,220 @@
+use nalgebra::Vector3;
+use simdnoise::{NoiseBuilder, Settings};
+
+use crate::common::component::{chunk, ChunkPos};
+
+use super::{cave::CaveMap, ChunkGenerator, NumRange, WorldGenerator};
+
+// hills from 2d noise with water below a fixed level, caves from 3d noise below the
+// surface and solid stone further down; heights are in voxels
+#[derive(Debug, Clone)]
+pub struct NoiseGenerator {
+    pub height_seed: i32,
+    pub grass_seed: i32,
+    pub cave_seed: u64,
+    pub water: f32,
+    pub grass: f32,
+    // the surface never goes above this
+    pub top: f32,
+    // everything below this is stone
+    pub deep: f32,
+    // underground voxels where the cave noise is below this are hollow, and flooded if
+    // they're below the water level
+    pub cave: f32,
+}
+
+impl Default for NoiseGenerator {
+    fn default() -> Self {
+        let side_len = chunk::SIDE_LENGTH as f32;
+        Self {
+            height_seed: 0,
+            grass_seed: 1,
+            cave_seed: 2,
+            water: 0.18 * side_len,
+            grass: 0.35 * side_len,
+            top: 0.5 * side_len,
+            deep: -2.0 * side_len,
+            cave: 0.3,
+        }
+    }
+}
+
+impl WorldGenerator for NoiseGenerator {
+    fn name(&self) -> &'static str {
+        "noise"
+    }
+    fn version(&self) -> u32 {
+        3
+    }
+    fn uniform(&self, pos: ChunkPos) -> Option<u32> {
+        let y = pos.origin().y as f32;
+        if y >= self.top.max(self.water + 1.0) {
+            Some(0)
+        } else if y + chunk::SIDE_LENGTH as f32 <= self.deep {
+            Some(1)
+        } else {
+            None
+        }
+    }
+    fn chunk(&self, pos: ChunkPos) -> Box<dyn ChunkGenerator + '_> {
+        let posf: Vector3<f32> = pos.origin().cast();
+        let top = self.top;
+        let grass = self.grass;
+        Box::new(NoiseChunk {
+            settings: self,
+            posf,
+            height: generate_noise_map(self.height_seed, 1.0, posf, chunk::SCALE, &mut |v: f32| {
+                (v * 2.0).exp2() * top * 0.25
+            }),
+            grass: generate_noise_map(self.grass_seed, 50.0, posf, chunk::SCALE, &mut |v: f32| {
+                v * 20.0 + grass
+            }),
+            caves: CaveMap::new(self.cave_seed, pos),
+        })
+    }
+}
+
+struct NoiseChunk<'a> {
+    settings: &'a NoiseGenerator,
+    posf: Vector3<f32>,
+    height: NoiseMap,
+    grass: NoiseMap,
+    caves: CaveMap,
+}
+
+impl ChunkGenerator for NoiseChunk<'_> {
+    // 0 air 1 stone 2 grass 3 water
+    fn leaf(&self, p: Vector3<usize>) -> u32 {
+        let s = self.settings;
+        let y = p.y as f32 + self.posf.y;
+        if y < s.deep {
+            return 1;
+        }
+        let n = self.height.base[p.x + p.z * chunk::SIDE_LENGTH];
+        let n2 = self.grass.base[p.x + p.z * chunk::SIDE_LENGTH];
+        if y < n {
+            if self.caves.get(p) < s.cave {
+                if y <= s.water {
+                    3
+                } else {
+                    0
+                }
+            } else if y < s.water {
+                1
+            } else if y < n2 {
+                2
+            } else {
+                1
+            }
+        } else if y <= s.water {
+            3
+        } else {
+            0
+        }
+    }
+
+    // 0 air 1 stone 2 grass 3 water
+    fn node(&self, p: Vector3<usize>, scale: u32) -> Option<u32> {
+        let s = self.settings;
+        let side_len = 2usize.pow(scale);
+        let y = NumRange {
+            min: p.y as f32 + self.posf.y,
+            max: (p.y + side_len - 1) as f32 + self.posf.y,
+        };
+        if y.max < s.deep {
+            return Some(1);
+        } else if y.min < s.deep {
+            return None;
+        }
+        let l = scale as usize - 1;
+        let i = (p.x >> scale) + (p.z >> scale) * (chunk::SIDE_LENGTH / side_len);
+        let n = &self.height.levels[l][i];
+        let n2 = &self.grass.levels[l][i];
+        Some(if y.max < n.min {
+            // all underground, so caves decide unless the node is clear of them
+            let cave = self.caves.range(p, scale);
+            if cave.max < s.cave {
+                if y.max <= s.water {
+                    3
+                } else if y.min > s.water {
+                    0
+                } else {
+                    return None;
+                }
+            } else if cave.min < s.cave {
+                return None;
+            } else if y.max < s.water {
+                1
+            } else if y.max < n2.min && y.min >= s.water {
+                2
+            } else if y.min > n2.max {
+                1
+            } else {
+                return None;
+            }
+        } else if y.max <= s.water && y.min > n.max {
+            3
+        } else if y.min > s.water && y.min > n.max {
+            0
+        } else {
+            return None;
+        })
+    }
+}
+
+fn generate_noise_map(
+    seed: i32,
+    freq: f32,
+    posf: Vector3<f32>,
+    levels: u32,
+    adjust: &mut impl FnMut(f32) -> f32,
+) -> NoiseMap {
+    let mut size = 2usize.pow(levels);
+    let (mut base, min, max) = NoiseBuilder::gradient_2d_offset(posf.x, size, posf.z, size)
+        .with_seed(seed)
+        .with_freq(freq / (size as f32))
+        .generate();
+    for v in &mut base {
+        *v = adjust((*v - min) / (max - min));
+    }
+    let first_len = base.len() / 4;
+    let mut first = Vec::with_capacity(first_len);
+    for y in (0..size).step_by(2) {
+        for x in (0..size).step_by(2) {
+            let a = base[x + y * size];
+            let b = base[x + 1 + y * size];
+            let c = base[x + (y + 1) * size];
+            let d = base[x + 1 + (y + 1) * size];
+            first.push(NumRange {
+                min: a.min(b).min(c).min(d),
+                max: a.max(b).max(c).max(d),
+            })
+        }
+    }
+    let mut arr = vec![first];
+    for l in 1..levels as usize {
+        size /= 2;
+        let prev = &arr[l - 1];
+        let mut new = Vec::with_capacity(prev.len() / 4);
+        for y in (0..size).step_by(2) {
+            for x in (0..size).step_by(2) {
+                let a = &prev[x + y * size];
+                let b = &prev[x + 1 + y * size];
+                let c = &prev[x + (y + 1) * size];
+                let d = &prev[x + 1 + (y + 1) * size];
+                new.push(NumRange {
+                    min: a.min.min(b.min).min(c.min).min(d.min),
+                    max: a.max.max(b.max).max(c.max).max(d.max),
+                })
+            }
+        }
+        arr.push(new);
+    }
+    NoiseMap { base, levels: arr }
+}
+
+#[derive(Debug)]
+pub struct NoiseMap {
+    levels: Vec<Vec<NumRange>>,
+    base: Vec<f32>,
+}
@@ -0,0 +1,32 @@
+use nalgebra::Vector3;
+
+use crate::common::component::ChunkPos;
+
+use super::{ChunkGenerator, WorldGenerator};
+
+// nothing but air
+pub struct VoidGenerator;
+
+impl WorldGenerator for VoidGenerator {
+    fn name(&self) -> &'static str {
+        "void"
+    }
+    fn version(&self) -> u32 {
+        1
+    }
+    fn uniform(&self, _pos: ChunkPos) -> Option<u32> {
+        Some(0)
+    }
+    fn chunk(&self, _pos: ChunkPos) -> Box<dyn ChunkGenerator + '_> {
+        Box::new(VoidGenerator)
+    }
+}
+
+impl ChunkGenerator for VoidGenerator {
+    fn leaf(&self, _p: Vector3<usize>) -> u32 {
+        0
+    }
+    fn node(&self, _p: Vector3<usize>, _scale: u32) -> Option<u32> {
+        Some(0)
+    }
+}
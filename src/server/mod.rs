@@ -1,24 +1,35 @@
 mod chunk;
 mod client;
+mod edit;
+mod generation;
+mod region;
 mod rsc;
+mod save;
 mod system;
 mod test;
-mod generation;
 
 pub use client::*;
 
 use crate::common::{
     component::{
-        ChunkBundle, ChunkData, ChunkMap, ChunkMesh, ChunkPos, Orientation, PlayerBundle, Pos,
-        VoxelGrid, VoxelGridBundle,
+        ChunkMap, Orientation, Player, PlayerBundle, Pos, ViewDistance, VoxelGrid, VoxelGridBundle,
     },
     ClientChannel, ClientMessage, ServerMessage,
 };
 use bevy_ecs::{entity::Entity, system::SystemId, world::World};
 use chunk::ChunkManager;
 use client::{ClientBroadcast, ServerClient, ServerClients};
-use rsc::UPDATE_TIME;
-use std::time::{Duration, Instant};
+use generation::WorldGenerator;
+use nalgebra::Matrix3;
+use rsc::{DEFAULT_GENERATOR, GENERATOR_VAR, MAX_SPEED, UPDATE_TIME, WORLD_DIR};
+use save::{SavedGrid, SavedPlayer, WorldSave};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use test::spawn_test_stuff;
 
 pub struct Server {
@@ -28,6 +39,12 @@ pub struct Server {
     world: World,
     systems: ServerSystems,
     mov: Vec<Entity>,
+    // the latest move of every player since the last tick, so clients can't move
+    // their player more often than the server updates; a player that's further away
+    // than MAX_SPEED allows in a tick stays here and keeps moving towards it
+    moves: HashMap<Entity, (Pos, Orientation)>,
+    // players from the loaded save that nobody with their name has joined as yet
+    saved_players: Vec<SavedPlayer>,
     stop: bool,
 }
 
@@ -50,7 +67,10 @@ impl Server {
         let mut world = World::new();
         world.insert_resource(ClientBroadcast::new());
         world.insert_resource(ChunkMap::new());
-        world.insert_non_send_resource(ChunkManager::new());
+        world.insert_non_send_resource(ChunkManager::new(
+            &Path::new(WORLD_DIR).join("regions"),
+            Self::pick_generator(),
+        ));
         let systems = ServerSystems::new(&mut world);
         Self {
             clients: ServerClients::new(),
@@ -59,10 +79,26 @@ impl Server {
             target: Instant::now(),
             update_time: UPDATE_TIME,
             mov: Vec::new(),
+            moves: HashMap::new(),
+            saved_players: Vec::new(),
             stop: false,
         }
     }
 
+    fn pick_generator() -> Arc<dyn WorldGenerator> {
+        let name = std::env::var(GENERATOR_VAR).unwrap_or_else(|_| DEFAULT_GENERATOR.to_owned());
+        let generator = generation::generator(&name).unwrap_or_else(|| {
+            println!(
+                "unknown generator {:?}, expected one of {:?}",
+                name,
+                generation::GENERATORS
+            );
+            generation::generator(DEFAULT_GENERATOR).unwrap()
+        });
+        println!("generating terrain with {}", generator.name());
+        generator
+    }
+
     pub fn from_client(client: ClientChannel) -> Self {
         let mut s = Self::new();
         s.add_client(ServerClient::Local(client));
@@ -79,7 +115,14 @@ impl Server {
     }
 
     pub fn run(&mut self) {
-        spawn_test_stuff(&mut self.world);
+        match self.load_world() {
+            Ok(true) => (),
+            Ok(false) => spawn_test_stuff(&mut self.world),
+            Err(err) => {
+                println!("failed to load world: {}", err);
+                spawn_test_stuff(&mut self.world);
+            }
+        }
         loop {
             self.recv();
             let now = Instant::now();
@@ -88,12 +131,66 @@ impl Server {
                 self.tick();
             }
             if self.stop {
+                if let Err(err) = self.save_world() {
+                    println!("failed to save world: {}", err);
+                }
                 break;
             }
             self.send();
         }
     }
 
+    fn save_path() -> PathBuf {
+        Path::new(WORLD_DIR).join("entities.bin")
+    }
+
+    // terrain doesn't need saving here, chunks get written to their region files as they change
+    pub fn save_world(&mut self) -> io::Result<()> {
+        let mut q = self
+            .world
+            .query::<(Entity, &Pos, &Orientation, &VoxelGrid)>();
+        let grids = q
+            .iter(&self.world)
+            .map(|(e, p, o, g)| SavedGrid {
+                grid: VoxelGridBundle {
+                    pos: *p,
+                    orientation: *o,
+                    grid: g.clone(),
+                },
+                moving: self.mov.contains(&e),
+            })
+            .collect();
+        let mut q = self
+            .world
+            .query::<(&Player, &Pos, &Orientation)>();
+        let players = q
+            .iter(&self.world)
+            .map(|(player, p, o)| SavedPlayer {
+                name: player.name.clone(),
+                pos: *p,
+                orientation: *o,
+            })
+            .chain(self.saved_players.iter().cloned())
+            .collect();
+        WorldSave { grids, players }.save(&Self::save_path())
+    }
+
+    // only run at startup, so terrain and entities come from the same point: spawns the
+    // saved grids and keeps the players for when they join again, false if there's no save
+    pub fn load_world(&mut self) -> io::Result<bool> {
+        let Some(save) = WorldSave::load(&Self::save_path())? else {
+            return Ok(false);
+        };
+        for SavedGrid { grid, moving } in save.grids {
+            let e = self.world.spawn(grid).id();
+            if moving {
+                self.mov.push(e);
+            }
+        }
+        self.saved_players = save.players;
+        Ok(true)
+    }
+
     pub fn tick(&mut self) {
         let mut q = self.world.query::<(Entity, &mut Pos)>();
         for (e, mut p) in q.iter_mut(&mut self.world) {
@@ -101,16 +198,42 @@ impl Server {
                 p.x += 0.1;
             }
         }
+        let max_step = MAX_SPEED * self.update_time.as_secs_f32();
+        self.moves.retain(|e, (target, orientation)| {
+            if is_rotation(orientation) {
+                if let Some(mut o) = self.world.get_mut::<Orientation>(*e) {
+                    *o = *orientation;
+                }
+            }
+            // only players that joined have a position
+            let Some(mut p) = self.world.get_mut::<Pos>(*e) else {
+                return false;
+            };
+            let step = **target - **p;
+            let dist = step.norm();
+            // a nan or infinite position is never going to be reached
+            if !dist.is_finite() {
+                false
+            } else if dist <= max_step {
+                *p = *target;
+                false
+            } else {
+                p.0 += step * (max_step / dist);
+                true
+            }
+        });
         self.world.run_system(self.systems.sync_pos).unwrap();
         self.world.run_system(self.systems.sync_chunks).unwrap();
         self.world.clear_trackers();
     }
 
     pub fn recv(&mut self) {
+        let mut save = false;
+        let mut edits = Vec::new();
         for (id, client) in &mut self.clients {
             for msg in client.recv() {
                 match msg {
-                    ServerMessage::Join => {
+                    ServerMessage::Join(name) => {
                         let mut q = self
                             .world
                             .query::<(Entity, &Pos, &Orientation, &VoxelGrid)>();
@@ -125,20 +248,16 @@ impl Server {
                                 },
                             ))
                         }
-                        let mut q = self
-                            .world
-                            .query::<(Entity, &ChunkPos, &ChunkData, &ChunkMesh)>();
-                        for (e, p, c, m) in q.iter(&self.world) {
-                            client.send(ClientMessage::LoadChunk(
-                                e,
-                                ChunkBundle {
-                                    pos: *p,
-                                    data: c.clone(),
-                                    mesh: m.clone(),
-                                },
-                            ))
+                        // chunks get sent by sync::chunks, which keeps track of what the
+                        // player has loaded
+                        let saved = take_saved(&mut self.saved_players, &name);
+                        let mut player = PlayerBundle::new(name);
+                        if let Some(saved) = saved {
+                            player.pos = saved.pos;
+                            player.orientation = saved.orientation;
+                            client.send(ClientMessage::MovePlayer(saved.pos, saved.orientation));
                         }
-                        self.world.entity_mut(*id).insert(PlayerBundle::new());
+                        self.world.entity_mut(*id).insert(player);
                     }
                     ServerMessage::SpawnVoxelGrid(grid) => {
                         let e = self.world.spawn(grid.clone()).id();
@@ -147,12 +266,31 @@ impl Server {
                             .resource_mut::<ClientBroadcast>()
                             .send(ClientMessage::SpawnVoxelGrid(e, grid));
                     }
+                    ServerMessage::PlayerMove(pos, orientation) => {
+                        self.moves.insert(*id, (pos, orientation));
+                    }
+                    ServerMessage::ViewDistance(dist) => {
+                        if let Some(mut d) = self.world.get_mut::<ViewDistance>(*id) {
+                            *d = dist.clamped();
+                        }
+                    }
+                    ServerMessage::EditVoxels(e) => edits.push((*id, e)),
+                    ServerMessage::SaveWorld => save = true,
                     ServerMessage::Stop => {
                         self.stop = true;
                     }
                 }
             }
         }
+        for (player, edits) in edits {
+            edit::edit_voxels(&mut self.world, player, edits);
+        }
+        if save {
+            match self.save_world() {
+                Ok(()) => println!("saved world"),
+                Err(err) => println!("failed to save world: {}", err),
+            }
+        }
     }
 
     pub fn send(&mut self) {
@@ -172,3 +310,16 @@ impl Server {
         }
     }
 }
+
+// clients send the whole matrix, so it has to be checked to really be a rotation
+fn is_rotation(orientation: &Orientation) -> bool {
+    let m = orientation.matrix();
+    m.iter().all(|f| f.is_finite())
+        && (m * m.transpose() - Matrix3::identity()).amax() < 1e-3
+        && (m.determinant() - 1.0).abs() < 1e-3
+}
+
+fn take_saved(players: &mut Vec<SavedPlayer>, name: &str) -> Option<SavedPlayer> {
+    let i = players.iter().position(|p| p.name == name)?;
+    Some(players.remove(i))
+}
@@ -0,0 +1,352 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use nalgebra::Vector3;
+
+use crate::common::component::{ChunkData, ChunkPos};
+
+// A region file holds the edited chunks of a REGION_SIZE^3 cube of chunks, anything
+// else can just be generated again:
+//   magic "OREG", format version u16
+//   REGION_LEN entries of (offset u64, len u32, generator version u32),
+//     len 0 meaning the chunk isn't saved
+//   the chunks, each one encoded with ChunkData::encode
+// all little endian. Saving a chunk appends it to the file and then points its entry at
+// it, the old copy stays behind until there's more of those than chunks and the file gets
+// rewritten. Entries remember the generator version the chunk was saved with: chunks from
+// older versions are still loaded so a new generator never throws away edits, but ones
+// from a newer version than the running generator are neither loaded nor replaced, since
+// they can hold voxels this version doesn't know about
+pub const REGION_SIZE: i32 = 8;
+const REGION_LEN: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+const MAGIC: &[u8; 4] = b"OREG";
+const FORMAT_VERSION: u16 = 2;
+const HEADER_LEN: usize = 4 + 2;
+const ENTRY_LEN: usize = 8 + 4 + 4;
+const DATA_START: u64 = (HEADER_LEN + REGION_LEN * ENTRY_LEN) as u64;
+// bytes of replaced chunks a file can hold before it's worth rewriting
+const MIN_GARBAGE: u64 = 1 << 20;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Entry {
+    offset: u64,
+    len: u32,
+    generator: u32,
+}
+
+pub fn region_of(pos: ChunkPos) -> (Vector3<i32>, usize) {
+    let region = pos.map(|c| c.div_euclid(REGION_SIZE));
+    let local = pos.map(|c| c.rem_euclid(REGION_SIZE) as usize);
+    let size = REGION_SIZE as usize;
+    (region, local.x * size * size + local.y * size + local.z)
+}
+
+fn region_path(dir: &Path, region: Vector3<i32>) -> PathBuf {
+    dir.join(format!("r.{}.{}.{}.bin", region.x, region.y, region.z))
+}
+
+// None if the chunk was never saved, can't be read or is from a newer generator
+pub fn load_chunk(dir: &Path, pos: ChunkPos, generator: u32) -> Option<ChunkData> {
+    let (region, i) = region_of(pos);
+    let mut file = match File::open(region_path(dir, region)) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            println!("can't open region {:?}: {}", region, err);
+            return None;
+        }
+    };
+    let entry = match read_table(&mut file) {
+        Ok(table) => table[i],
+        Err(err) => {
+            println!("can't read region {:?}: {}", region, err);
+            return None;
+        }
+    };
+    if entry.len != 0 && entry.generator > generator {
+        println!(
+            "chunk {:?} was saved by generator version {}, newer than {}, not loading it",
+            *pos, entry.generator, generator
+        );
+        return None;
+    } else if entry.len != 0 && entry.generator < generator {
+        println!(
+            "chunk {:?} was edited on generator version {}, keeping it",
+            *pos, entry.generator
+        );
+    }
+    let bytes = match read_chunk(&mut file, entry) {
+        Ok(bytes) => bytes?,
+        Err(err) => {
+            println!(
+                "can't read chunk {:?} in region {:?}: {}",
+                *pos, region, err
+            );
+            return None;
+        }
+    };
+    match ChunkData::decode(&bytes) {
+        Ok(chunk) => Some(chunk),
+        Err(err) => {
+            println!("bad chunk {:?} in region {:?}: {}", *pos, region, err);
+            None
+        }
+    }
+}
+
+// replaces the given chunks in the region, which gets created if it doesn't exist yet;
+// chunks saved by a newer generator are left as they are
+pub fn save_chunks(
+    dir: &Path,
+    region: Vector3<i32>,
+    chunks: &[(usize, Vec<u8>)],
+    generator: u32,
+) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let path = region_path(dir, region);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+    let mut table = if file.metadata()?.len() == 0 {
+        let table = vec![Entry::default(); REGION_LEN];
+        file.write_all(MAGIC)?;
+        file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        file.write_all(&encode_table(&table))?;
+        table
+    } else {
+        match read_table(&mut file) {
+            Ok(table) => table,
+            Err(err) => {
+                // it could still hold edits, so it's kept next to the new one
+                drop(file);
+                let backup = backup_path(&path);
+                println!(
+                    "can't read region {:?} ({}), moving it to {:?}",
+                    region, err, backup
+                );
+                std::fs::rename(&path, &backup)?;
+                return save_chunks(dir, region, chunks, generator);
+            }
+        }
+    };
+    let chunks: Vec<_> = chunks
+        .iter()
+        .filter(|(i, _)| {
+            let newer = table[*i].len != 0 && table[*i].generator > generator;
+            if newer {
+                println!(
+                    "keeping chunk {} in region {:?}, it's from newer generator version {}",
+                    i, region, table[*i].generator
+                );
+            }
+            !newer
+        })
+        .collect();
+    let mut end = file.seek(SeekFrom::End(0))?;
+    for (i, bytes) in &chunks {
+        file.write_all(bytes)?;
+        table[*i] = Entry {
+            offset: end,
+            len: bytes.len() as u32,
+            generator,
+        };
+        end += bytes.len() as u64;
+    }
+    // the chunks have to be on disk before any entry points at them
+    file.sync_data()?;
+    for (i, _) in &chunks {
+        file.seek(SeekFrom::Start((HEADER_LEN + i * ENTRY_LEN) as u64))?;
+        file.write_all(&encode_table(&table[*i..*i + 1]))?;
+    }
+    file.sync_data()?;
+    let live = table.iter().map(|e| e.len as u64).sum::<u64>();
+    if (end - DATA_START).saturating_sub(live) > live.max(MIN_GARBAGE) {
+        compact(&path, &mut file, &table)?;
+    }
+    Ok(())
+}
+
+// rewrites the region with only the chunks the table points at, through a temporary
+// file so readers only ever see the old or the new version
+fn compact(path: &Path, file: &mut File, table: &[Entry]) -> io::Result<()> {
+    let mut new_table = table.to_vec();
+    let mut chunks = Vec::new();
+    for entry in &mut new_table {
+        if let Some(bytes) = read_chunk(file, *entry)? {
+            entry.offset = DATA_START + chunks.len() as u64;
+            chunks.extend_from_slice(&bytes);
+        }
+    }
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&encode_table(&new_table));
+    out.extend_from_slice(&chunks);
+    let tmp = path.with_extension("tmp");
+    File::create(&tmp)?.write_all(&out)?;
+    std::fs::rename(tmp, path)
+}
+
+// r.0.0.0.bin becomes r.0.0.0.bak, or r.0.0.0.bak1 and so on if that's taken
+fn backup_path(path: &Path) -> PathBuf {
+    (0..)
+        .map(|i| match i {
+            0 => path.with_extension("bak"),
+            i => path.with_extension(format!("bak{}", i)),
+        })
+        .find(|p| !p.exists())
+        .unwrap()
+}
+
+fn read_chunk(file: &mut File, entry: Entry) -> io::Result<Option<Vec<u8>>> {
+    if entry.len == 0 {
+        return Ok(None);
+    }
+    let mut bytes = vec![0; entry.len as usize];
+    file.seek(SeekFrom::Start(entry.offset))?;
+    file.read_exact(&mut bytes)?;
+    Ok(Some(bytes))
+}
+
+fn encode_table(table: &[Entry]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(table.len() * ENTRY_LEN);
+    for entry in table {
+        out.extend_from_slice(&entry.offset.to_le_bytes());
+        out.extend_from_slice(&entry.len.to_le_bytes());
+        out.extend_from_slice(&entry.generator.to_le_bytes());
+    }
+    out
+}
+
+fn read_table(file: &mut File) -> io::Result<Vec<Entry>> {
+    let mut header = [0; HEADER_LEN];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+    if &header[0..4] != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a region file",
+        ));
+    }
+    let version = u16::from_le_bytes(header[4..6].try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported region version {}", version),
+        ));
+    }
+    let mut table = vec![0; REGION_LEN * ENTRY_LEN];
+    file.read_exact(&mut table)?;
+    Ok(table
+        .chunks_exact(ENTRY_LEN)
+        .map(|e| Entry {
+            offset: u64::from_le_bytes(e[0..8].try_into().unwrap()),
+            len: u32::from_le_bytes(e[8..12].try_into().unwrap()),
+            generator: u32::from_le_bytes(e[12..16].try_into().unwrap()),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common::component::chunk, util::oct_tree::OctTree};
+
+    // a fresh directory per test so they can run in parallel
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("region_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn read_all(dir: &Path, region: Vector3<i32>) -> Vec<Option<Vec<u8>>> {
+        let mut file = File::open(region_path(dir, region)).unwrap();
+        let table = read_table(&mut file).unwrap();
+        table
+            .into_iter()
+            .map(|e| read_chunk(&mut file, e).unwrap())
+            .collect()
+    }
+
+    fn file_len(dir: &Path, region: Vector3<i32>) -> u64 {
+        std::fs::metadata(region_path(dir, region)).unwrap().len()
+    }
+
+    #[test]
+    fn append_replaces_in_place() {
+        let dir = test_dir("append");
+        let region = Vector3::new(-1, 0, 2);
+        save_chunks(&dir, region, &[(0, vec![1; 10]), (5, vec![2; 20])], 1).unwrap();
+        assert_eq!(file_len(&dir, region), DATA_START + 30);
+        save_chunks(&dir, region, &[(5, vec![3; 7])], 1).unwrap();
+        // the new copy is appended and only its entry changes
+        assert_eq!(file_len(&dir, region), DATA_START + 37);
+        let chunks = read_all(&dir, region);
+        assert_eq!(chunks[0], Some(vec![1; 10]));
+        assert_eq!(chunks[5], Some(vec![3; 7]));
+        assert_eq!(chunks.iter().flatten().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compaction_drops_old_copies() {
+        let dir = test_dir("compact");
+        let region = Vector3::zeros();
+        let big = 1 << 17;
+        save_chunks(&dir, region, &[(1, vec![7; 100])], 1).unwrap();
+        for i in 0..40u8 {
+            save_chunks(&dir, region, &[(2, vec![i; big])], 1).unwrap();
+            let len = file_len(&dir, region);
+            assert!(len <= DATA_START + 100 + big as u64 + 2 * MIN_GARBAGE, "{len}");
+        }
+        let chunks = read_all(&dir, region);
+        assert_eq!(chunks[1], Some(vec![7; 100]));
+        assert_eq!(chunks[2], Some(vec![39; big]));
+        // 40 copies would be 5 MiB without compaction
+        assert!(file_len(&dir, region) < 4 * MIN_GARBAGE);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_region_is_backed_up() {
+        let dir = test_dir("backup");
+        let region = Vector3::new(0, -3, 0);
+        let path = region_path(&dir, region);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, b"not a region").unwrap();
+        save_chunks(&dir, region, &[(3, vec![4; 5])], 1).unwrap();
+        assert_eq!(std::fs::read(path.with_extension("bak")).unwrap(), b"not a region");
+        assert_eq!(read_all(&dir, region)[3], Some(vec![4; 5]));
+        // a second bad file doesn't overwrite the first backup
+        std::fs::write(&path, b"OREG\x09\x00").unwrap();
+        save_chunks(&dir, region, &[(3, vec![5; 5])], 1).unwrap();
+        assert_eq!(std::fs::read(path.with_extension("bak")).unwrap(), b"not a region");
+        assert_eq!(std::fs::read(path.with_extension("bak1")).unwrap(), b"OREG\x09\x00");
+        assert_eq!(read_all(&dir, region)[3], Some(vec![5; 5]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn generator_versions() {
+        let dir = test_dir("versions");
+        let pos = ChunkPos::new(9, -1, 4);
+        let (region, i) = region_of(pos);
+        let tree = |v| ChunkData::from_tree(OctTree::from_leaf(v, chunk::SCALE));
+        save_chunks(&dir, region, &[(i, tree(1).encode())], 2).unwrap();
+        // older and equal versions load, a newer one is left alone
+        assert_eq!(load_chunk(&dir, pos, 2).unwrap().get(Vector3::zeros()), 1);
+        assert_eq!(load_chunk(&dir, pos, 3).unwrap().get(Vector3::zeros()), 1);
+        assert!(load_chunk(&dir, pos, 1).is_none());
+        save_chunks(&dir, region, &[(i, tree(2).encode())], 1).unwrap();
+        assert_eq!(load_chunk(&dir, pos, 2).unwrap().get(Vector3::zeros()), 1);
+        save_chunks(&dir, region, &[(i, tree(3).encode())], 3).unwrap();
+        assert_eq!(load_chunk(&dir, pos, 3).unwrap().get(Vector3::zeros()), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
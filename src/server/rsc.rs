@@ -2,3 +2,12 @@ use std::time::Duration;
 
 pub const UPS: u32 = 60;
 pub const UPDATE_TIME: Duration = Duration::from_millis(1000 / UPS as u64);
+// in voxels per second, how fast a player can move no matter what their client says
+pub const MAX_SPEED: f32 = 1024.0;
+// in voxels, how far from a player the voxels they edit can be
+pub const MAX_REACH: f32 = 256.0;
+
+pub const WORLD_DIR: &str = "world";
+// the generator new chunks come from, can be changed with the environment variable
+pub const DEFAULT_GENERATOR: &str = "noise";
+pub const GENERATOR_VAR: &str = "VOXELGAME_GENERATOR";
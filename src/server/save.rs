@@ -0,0 +1,180 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use nalgebra::{Matrix3, Rotation3, Vector3};
+use ndarray::Array3;
+
+use crate::{
+    client::render::voxel::VoxelColor,
+    common::component::{Orientation, Pos, VoxelGrid, VoxelGridBundle},
+};
+
+// Everything in the world that isn't terrain, which lives in the region files:
+//   magic "OSAV", format version u16
+//   grid count u32, then per grid: pos, orientation, moving u8, dimensions 3 x u32
+//     and the voxels as rgba in the grid's (x, y, z) order
+//   player count u32, then per player: name length u32 and the name as utf-8, pos,
+//     orientation
+// positions are 3 x f32, orientations the 9 f32 of the rotation matrix in column order,
+// all little endian
+const MAGIC: &[u8; 4] = b"OSAV";
+const FORMAT_VERSION: u16 = 2;
+
+pub struct WorldSave {
+    pub grids: Vec<SavedGrid>,
+    pub players: Vec<SavedPlayer>,
+}
+
+pub struct SavedGrid {
+    pub grid: VoxelGridBundle,
+    pub moving: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SavedPlayer {
+    pub name: String,
+    pub pos: Pos,
+    pub orientation: Orientation,
+}
+
+impl WorldSave {
+    // None if the world was never saved
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        let mut bytes = Vec::new();
+        match File::open(path) {
+            Ok(mut file) => file.read_to_end(&mut bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        Self::decode(&bytes).map(Some)
+    }
+
+    // through a temporary file so a crash can't leave half a save behind
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        File::create(&tmp)?.write_all(&self.encode())?;
+        std::fs::rename(tmp, path)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.grids.len() as u32).to_le_bytes());
+        for SavedGrid { grid, moving } in &self.grids {
+            write_transform(&mut out, &grid.pos, &grid.orientation);
+            out.push(*moving as u8);
+            let (x, y, z) = grid.grid.dim();
+            for dim in [x, y, z] {
+                out.extend_from_slice(&(dim as u32).to_le_bytes());
+            }
+            for color in grid.grid.iter() {
+                out.extend_from_slice(bytemuck::bytes_of(color));
+            }
+        }
+        out.extend_from_slice(&(self.players.len() as u32).to_le_bytes());
+        for player in &self.players {
+            out.extend_from_slice(&(player.name.len() as u32).to_le_bytes());
+            out.extend_from_slice(player.name.as_bytes());
+            write_transform(&mut out, &player.pos, &player.orientation);
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Self> {
+        let mut r = Reader { bytes };
+        if r.take(4)? != MAGIC {
+            return Err(invalid("not a world save"));
+        }
+        let version = u16::from_le_bytes(r.array()?);
+        if version != FORMAT_VERSION {
+            return Err(invalid(format!("unsupported save version {}", version)));
+        }
+        let mut grids = Vec::new();
+        for _ in 0..r.u32()? {
+            let (pos, orientation) = r.transform()?;
+            let moving = r.take(1)?[0] != 0;
+            let dim = (r.u32()? as usize, r.u32()? as usize, r.u32()? as usize);
+            let size = std::mem::size_of::<VoxelColor>();
+            let len = [dim.0, dim.1, dim.2]
+                .into_iter()
+                .try_fold(size, usize::checked_mul)
+                .ok_or_else(|| invalid("grid too large"))?;
+            let colors = r
+                .take(len)?
+                .chunks_exact(size)
+                .map(bytemuck::pod_read_unaligned)
+                .collect();
+            let data = Array3::from_shape_vec(dim, colors).map_err(invalid)?;
+            grids.push(SavedGrid {
+                grid: VoxelGridBundle {
+                    pos,
+                    orientation,
+                    grid: VoxelGrid::new(data),
+                },
+                moving,
+            });
+        }
+        let mut players = Vec::new();
+        for _ in 0..r.u32()? {
+            let len = r.u32()? as usize;
+            let name = String::from_utf8(r.take(len)?.to_vec()).map_err(invalid)?;
+            let (pos, orientation) = r.transform()?;
+            players.push(SavedPlayer {
+                name,
+                pos,
+                orientation,
+            });
+        }
+        Ok(Self { grids, players })
+    }
+}
+
+fn write_transform(out: &mut Vec<u8>, pos: &Pos, orientation: &Orientation) {
+    for f in pos.iter().chain(orientation.matrix().iter()) {
+        out.extend_from_slice(&f.to_le_bytes());
+    }
+}
+
+fn invalid(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (start, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(start)
+    }
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+    fn transform(&mut self) -> io::Result<(Pos, Orientation)> {
+        let pos = Vector3::new(self.f32()?, self.f32()?, self.f32()?);
+        let mut m = [0.0; 9];
+        for f in &mut m {
+            *f = self.f32()?;
+        }
+        let rot = Rotation3::from_matrix_unchecked(Matrix3::from_column_slice(&m));
+        Ok((pos.into(), rot.into()))
+    }
+}
@@ -4,12 +4,13 @@ use bevy_ecs::{
     system::{Commands, NonSendMut, Query, ResMut},
 };
 use nalgebra::Vector3;
+use std::collections::HashSet;
 
 use crate::{
     common::{
         component::{
-            chunk::{self, ChunkBundle, LoadedChunks},
-            ChunkData, ChunkMesh, ChunkPos, Player, Pos,
+            chunk::{ChunkBundle, LoadedChunks},
+            ChunkData, ChunkMesh, ChunkPos, Player, Pos, ViewDistance,
         },
         ClientMessage,
     },
@@ -22,34 +23,26 @@ pub fn pos(query: Query<(Entity, &Pos), Changed<Pos>>, mut clients: ResMut<Clien
     }
 }
 
+// chunks only get unloaded once they're this many chunks past the load radius,
+// so moving back and forth over a chunk border doesn't keep reloading them
+const UNLOAD_MARGIN: i32 = 1;
+
 pub fn chunks(
-    mut players: Query<(&Pos, &mut LoadedChunks, &mut ClientComponent), With<Player>>,
+    mut players: Query<
+        (&Pos, &ViewDistance, &mut LoadedChunks, &mut ClientComponent),
+        With<Player>,
+    >,
     chunks: Query<(&ChunkPos, &ChunkData, &ChunkMesh)>,
     mut loader: NonSendMut<ChunkManager>,
     mut commands: Commands,
 ) {
-    for (pos, mut loaded, mut client) in &mut players {
-        let fp = **pos / chunk::SIDE_LENGTH as f32;
-        let player_chunk = Vector3::new(
-            fp.x.floor() as i32,
-            fp.y.floor() as i32,
-            fp.z.floor() as i32,
-        );
-        let radius: i32 = 1;
-        let width = radius * 2 - 1;
-        let mut desired = Vec::new();
-        for i in 0..width.pow(3) {
-            let pos = Vector3::new(i % width, (i / width) % width, i / (width.pow(2)))
-                - Vector3::from_element(radius - 1);
-            let dist = pos.cast::<f32>().norm();
-            if dist < radius as f32 {
-                desired.push((dist, pos));
-            }
-        }
-        desired.sort_by(|(da, ..), (db, ..)| da.total_cmp(db));
-        let mut to_load = Vec::new();
-        for (_, pos) in desired {
-            let coords = pos - player_chunk;
+    let mut keep = HashSet::new();
+    for (pos, view_distance, mut loaded, mut client) in &mut players {
+        let player_chunk = ChunkPos::containing(**pos);
+        let radius = view_distance.0 as i32;
+        for pos in load_order(radius) {
+            let coords = *player_chunk + pos;
+            let priority = pos.dot(&pos);
             let pos = ChunkPos(coords);
             if !loaded.contains(&pos) {
                 if let Some(id) = loader.entity_at(&pos) {
@@ -64,15 +57,87 @@ pub fn chunks(
                     ));
                     loaded.insert(*pos);
                 } else {
-                    to_load.push(pos);
+                    loader.request(pos, priority);
                 }
             }
         }
-        for pos in to_load {
-            if !loader.try_load(pos) {
-                break;
+        let unload: Vec<ChunkPos> = loaded
+            .iter()
+            .filter(|pos| {
+                let offset = pos.0 - *player_chunk;
+                offset.dot(&offset) > (radius + UNLOAD_MARGIN).pow(2)
+            })
+            .copied()
+            .collect();
+        for pos in unload {
+            loaded.remove(&pos);
+            if let Some(id) = loader.entity_at(&pos) {
+                client.send(ClientMessage::UnloadChunk(*id));
             }
         }
+        keep.extend(loaded.iter().copied());
     }
+    loader.retain(&keep, &mut commands);
     loader.update(&mut commands);
 }
+
+// offsets of the chunks within radius of the center (inclusive), nearest first
+pub fn load_order(radius: i32) -> Vec<Vector3<i32>> {
+    let width = radius * 2 + 1;
+    let mut offsets: Vec<_> = (0..width.pow(3))
+        .map(|i| {
+            Vector3::new(i % width, (i / width) % width, i / width.pow(2))
+                - Vector3::from_element(radius)
+        })
+        .filter(|pos| pos.dot(pos) <= radius * radius)
+        .collect();
+    offsets.sort_by_key(|pos| pos.dot(pos));
+    offsets
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use nalgebra::Vector3;
+
+    use super::load_order;
+
+    #[test]
+    fn smallest_radii() {
+        assert_eq!(load_order(0), vec![Vector3::zeros()]);
+        let order = load_order(1);
+        assert_eq!(order.len(), 7);
+        assert_eq!(order[0], Vector3::zeros());
+        assert!(order[1..].iter().all(|pos| pos.abs().sum() == 1));
+    }
+
+    #[test]
+    fn near_to_far() {
+        for radius in 0..=6 {
+            let order = load_order(radius);
+            let distances: Vec<_> = order.iter().map(|pos| pos.dot(pos)).collect();
+            assert!(
+                distances.windows(2).all(|d| d[0] <= d[1]),
+                "radius {radius}"
+            );
+            let unique: HashSet<_> = order.iter().collect();
+            assert_eq!(unique.len(), order.len(), "radius {radius}");
+            // exactly the chunks in the sphere, in every direction
+            let r = radius;
+            let mut expected = 0;
+            for x in -r..=r {
+                for y in -r..=r {
+                    for z in -r..=r {
+                        let pos = Vector3::new(x, y, z);
+                        if pos.dot(&pos) <= r * r {
+                            expected += 1;
+                            assert!(unique.contains(&pos), "radius {radius} misses {pos}");
+                        }
+                    }
+                }
+            }
+            assert_eq!(order.len(), expected, "radius {radius}");
+        }
+    }
+}
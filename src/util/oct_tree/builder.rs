@@ -0,0 +1,468 @@
+use nalgebra::Vector3;
+use ndarray::ArrayView3;
+use rustc_hash::FxHashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{
+    children, corner_index, find_leaf, OctChange, OctDiff, OctNode, OctNodeEntry, OctNodeMap,
+    OctNodes, OctTree, CORNERS,
+};
+
+// fraction of the node array that can be garbage before an edit compacts the tree
+pub const DEFAULT_COMPACT_THRESHOLD: f32 = 0.5;
+
+// Owns the dedup map needed to build and edit a tree; build() hands out an immutable
+// OctTree that only has the node array, so it's cheap to clone and send around
+#[derive(Debug, Clone)]
+pub struct OctTreeBuilder {
+    pub(super) nodes: OctNodes,
+    levels: u32,
+    side_length: usize,
+    compact_threshold: f32,
+}
+
+impl OctTreeBuilder {
+    pub fn from_leaf(val: u32, levels: u32) -> Self {
+        Self {
+            nodes: OctNodes {
+                data: vec![OctNode::new_leaf(val)],
+                ..Default::default()
+            },
+            side_length: 2usize.pow(levels),
+            levels,
+            compact_threshold: DEFAULT_COMPACT_THRESHOLD,
+        }
+    }
+    pub fn from_leaf_fn(f_leaf: &mut impl FnMut(Vector3<usize>) -> u32, levels: u32) -> Self {
+        Self::from_fn(f_leaf, &mut |_, _| None, levels)
+    }
+    pub fn from_fn(
+        f_leaf: &mut impl FnMut(Vector3<usize>) -> u32,
+        f_node: &mut impl FnMut(Vector3<usize>, u32) -> Option<u32>,
+        levels: u32,
+    ) -> Self {
+        Self::from_fn_offset(f_leaf, f_node, levels, Vector3::from_element(0))
+    }
+    pub fn from_fn_offset(
+        f_leaf: &mut impl FnMut(Vector3<usize>) -> u32,
+        f_node: &mut impl FnMut(Vector3<usize>, u32) -> Option<u32>,
+        levels: u32,
+        offset: Vector3<usize>,
+    ) -> Self {
+        assert!(levels > 0);
+        let mut data = Vec::new();
+        let mut map = OctNodeMap::default();
+        data.push(OctNode::new_node(1));
+        Self::from_fn_offset_inner(f_leaf, f_node, &mut data, levels, offset, &mut map);
+        Self::from_data(data, map, levels)
+    }
+    // data starts with the root slot pointing at the block right after it,
+    // or holds a single leaf pushed after it when the whole tree is uniform
+    fn from_data(mut data: Vec<OctNode>, mut map: OctNodeMap, levels: u32) -> Self {
+        if data.len() == 2 {
+            data.remove(0);
+        } else {
+            map.insert(
+                data[1..9].try_into().unwrap(),
+                OctNodeEntry {
+                    node: data[0],
+                    count: 1,
+                },
+            );
+        }
+        Self {
+            nodes: OctNodes {
+                data,
+                map,
+                garbage: 0,
+            },
+            side_length: 2usize.pow(levels),
+            levels,
+            compact_threshold: DEFAULT_COMPACT_THRESHOLD,
+        }
+    }
+    fn from_fn_offset_inner(
+        f_leaf: &mut impl FnMut(Vector3<usize>) -> u32,
+        f_node: &mut impl FnMut(Vector3<usize>, u32) -> Option<u32>,
+        data: &mut Vec<OctNode>,
+        level: u32,
+        offset: Vector3<usize>,
+        map: &mut OctNodeMap,
+    ) {
+        if level == 1 {
+            let leaves: [OctNode; 8] =
+                core::array::from_fn(|i| OctNode::new_leaf(f_leaf(offset + CORNERS[i])));
+            push_leaves(data, map, leaves);
+            return;
+        }
+        let i = data.len();
+        data.resize(i + 8, OctNode::new_node(0));
+        let mut data_start = 0;
+        for (j, corner_offset) in CORNERS.iter().enumerate() {
+            let lvl = level - 1;
+            let pos = offset + corner_offset * 2usize.pow(lvl);
+            if let Some(leaf) = f_node(pos, lvl) {
+                data[i + j] = OctNode::new_leaf(leaf);
+            } else {
+                let sub_start = data.len();
+                Self::from_fn_offset_inner(f_leaf, f_node, data, lvl, pos, map);
+                data_start += attach_child(data, map, i + j, sub_start);
+            }
+        }
+        finish_group(data, map, i, data_start);
+    }
+    // same result as from_fn, but the subtrees below the top split levels (8 of them for
+    // a split of 1, 64 for 2) are built on worker threads and then merged in order
+    pub fn from_fn_par(
+        f_leaf: &(impl Fn(Vector3<usize>) -> u32 + Sync),
+        f_node: &(impl Fn(Vector3<usize>, u32) -> Option<u32> + Sync),
+        levels: u32,
+        split: u32,
+    ) -> Self {
+        if split == 0 || levels <= split {
+            return Self::from_fn(&mut |p| f_leaf(p), &mut |p, lvl| f_node(p, lvl), levels);
+        }
+        let sub_level = levels - split;
+        let mut jobs = Vec::new();
+        collect_subtrees(
+            f_node,
+            levels,
+            sub_level,
+            Vector3::from_element(0),
+            &mut jobs,
+        );
+        let threads = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(jobs.len())
+            .max(1);
+        let next = AtomicUsize::new(0);
+        let mut built: Vec<(usize, OctTreeBuilder)> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..threads)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut built = Vec::new();
+                        loop {
+                            let k = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(&pos) = jobs.get(k) else { break };
+                            let sub = Self::from_fn_offset(
+                                &mut |p| f_leaf(p),
+                                &mut |p, lvl| f_node(p, lvl),
+                                sub_level,
+                                pos,
+                            );
+                            built.push((k, sub));
+                        }
+                        built
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        built.sort_by_key(|(k, _)| *k);
+        let mut subtrees = built.into_iter().map(|(_, sub)| sub);
+        let mut data = vec![OctNode::new_node(1)];
+        let mut map = OctNodeMap::default();
+        Self::from_fn_par_inner(
+            f_node,
+            &mut data,
+            levels,
+            Vector3::from_element(0),
+            (sub_level, &mut subtrees),
+            &mut map,
+        );
+        Self::from_data(data, map, levels)
+    }
+    fn from_fn_par_inner(
+        f_node: &impl Fn(Vector3<usize>, u32) -> Option<u32>,
+        data: &mut Vec<OctNode>,
+        level: u32,
+        offset: Vector3<usize>,
+        (sub_level, subtrees): (u32, &mut impl Iterator<Item = OctTreeBuilder>),
+        map: &mut OctNodeMap,
+    ) {
+        if level == sub_level {
+            let sub = subtrees.next().unwrap();
+            replay(data, map, &sub.nodes.data, sub.nodes.data[0], level);
+            return;
+        }
+        let i = data.len();
+        data.resize(i + 8, OctNode::new_node(0));
+        let mut data_start = 0;
+        for (j, corner_offset) in CORNERS.iter().enumerate() {
+            let lvl = level - 1;
+            let pos = offset + corner_offset * 2usize.pow(lvl);
+            if let Some(leaf) = f_node(pos, lvl) {
+                data[i + j] = OctNode::new_leaf(leaf);
+            } else {
+                let sub_start = data.len();
+                Self::from_fn_par_inner(f_node, data, lvl, pos, (sub_level, subtrees), map);
+                data_start += attach_child(data, map, i + j, sub_start);
+            }
+        }
+        finish_group(data, map, i, data_start);
+    }
+    pub fn from_arr(arr: ArrayView3<u32>, levels: u32) -> Self {
+        Self::from_fn(&mut |p| arr[(p.x, p.y, p.z)], &mut |_, _| None, levels)
+    }
+    pub fn get(&self, pos: Vector3<usize>) -> u32 {
+        find_leaf(&self.nodes.data, self.nodes.data[0], self.levels, pos).val
+    }
+    pub fn set(&mut self, pos: Vector3<usize>, val: u32) {
+        self.set_region(pos, pos + Vector3::from_element(1), val);
+    }
+    // min is inclusive and max is exclusive, anything outside of the tree is ignored
+    pub fn set_region(&mut self, min: Vector3<usize>, max: Vector3<usize>, val: u32) {
+        let max = max.inf(&Vector3::from_element(self.side_length));
+        if min.zip_map(&max, |a, b| a >= b).iter().any(|b| *b) {
+            return;
+        }
+        let root = self.nodes.data[0];
+        self.nodes.data[0] =
+            self.set_region_inner(root, self.levels, Vector3::from_element(0), (min, max), val);
+        self.auto_compact();
+    }
+    fn set_region_inner(
+        &mut self,
+        node: OctNode,
+        level: u32,
+        offset: Vector3<usize>,
+        (min, max): (Vector3<usize>, Vector3<usize>),
+        val: u32,
+    ) -> OctNode {
+        let end = offset + Vector3::from_element(2usize.pow(level));
+        if (0..3).any(|i| end[i] <= min[i] || offset[i] >= max[i]) {
+            return node;
+        }
+        if (0..3).all(|i| min[i] <= offset[i] && end[i] <= max[i]) {
+            self.nodes.release(node);
+            return OctNode::new_leaf(val);
+        }
+        if node.is_leaf() && node.leaf_data() == val {
+            return node;
+        }
+        let mut children = self.nodes.children(node);
+        for child in children {
+            self.nodes.retain(child);
+        }
+        self.nodes.release(node);
+        let lvl = level - 1;
+        for (j, corner_offset) in CORNERS.iter().enumerate() {
+            let pos = offset + corner_offset * 2usize.pow(lvl);
+            children[j] = self.set_region_inner(children[j], lvl, pos, (min, max), val);
+        }
+        self.nodes.intern(children)
+    }
+    // pastes tree over the cube at pos, which has to be a multiple of the tree's side length
+    pub fn set_tree(&mut self, pos: Vector3<usize>, tree: &OctTree) {
+        assert!(tree.levels <= self.levels);
+        assert!(pos
+            .iter()
+            .all(|p| p % tree.side_length == 0 && *p < self.side_length));
+        let root = self.nodes.data[0];
+        self.nodes.data[0] = self.set_tree_inner(root, self.levels, Vector3::zeros(), pos, tree);
+        self.auto_compact();
+    }
+    fn set_tree_inner(
+        &mut self,
+        node: OctNode,
+        level: u32,
+        offset: Vector3<usize>,
+        pos: Vector3<usize>,
+        tree: &OctTree,
+    ) -> OctNode {
+        if level == tree.levels {
+            let mut moved = FxHashMap::default();
+            let new = self.nodes.copy_from(&tree.data, tree.data[0], &mut moved);
+            self.nodes.release(node);
+            return new;
+        }
+        let mut children = self.nodes.children(node);
+        for child in children {
+            self.nodes.retain(child);
+        }
+        self.nodes.release(node);
+        let lvl = level - 1;
+        let j = corner_index((pos - offset).map(|p| p >> lvl));
+        let child_offset = offset + CORNERS[j] * 2usize.pow(lvl);
+        children[j] = self.set_tree_inner(children[j], lvl, child_offset, pos, tree);
+        self.nodes.intern(children)
+    }
+    pub fn apply(&mut self, diff: &OctDiff) {
+        assert_eq!(diff.levels, self.levels);
+        for change in &diff.changes {
+            match change {
+                OctChange::Fill { pos, level, val } => {
+                    self.set_region(*pos, pos + Vector3::from_element(2usize.pow(*level)), *val)
+                }
+                OctChange::Replace { pos, tree } => self.set_tree(*pos, tree),
+            }
+        }
+    }
+    fn auto_compact(&mut self) {
+        if self.nodes.garbage as f32 > self.nodes.data.len() as f32 * self.compact_threshold {
+            self.compact();
+        }
+    }
+    pub fn raw(&self) -> &[OctNode] {
+        &self.nodes.data
+    }
+    pub fn levels(&self) -> u32 {
+        self.levels
+    }
+    // number of bytes in the node array that are no longer reachable from the root
+    pub fn wasted_bytes(&self) -> usize {
+        self.nodes.garbage * std::mem::size_of::<OctNode>()
+    }
+    pub fn compact_threshold(&self) -> f32 {
+        self.compact_threshold
+    }
+    pub fn set_compact_threshold(&mut self, threshold: f32) {
+        self.compact_threshold = threshold;
+    }
+    // rebuilds the node array from the root so only reachable groups are kept,
+    // returns the number of bytes reclaimed
+    pub fn compact(&mut self) -> usize {
+        let old = std::mem::take(&mut self.nodes);
+        self.nodes.data.push(old.data[0]);
+        let mut moved = FxHashMap::default();
+        self.nodes.data[0] = self.nodes.copy_from(&old.data, old.data[0], &mut moved);
+        self.nodes.data.shrink_to_fit();
+        (old.data.len() - self.nodes.data.len()) * std::mem::size_of::<OctNode>()
+    }
+    pub fn from_tree(tree: &OctTree) -> Self {
+        Self {
+            nodes: OctNodes::from_raw(tree.data.to_vec(), tree.levels)
+                .expect("trees are always valid"),
+            side_length: tree.side_length,
+            levels: tree.levels,
+            compact_threshold: DEFAULT_COMPACT_THRESHOLD,
+        }
+    }
+    pub fn build(&self) -> OctTree {
+        OctTree {
+            data: self.nodes.data.as_slice().into(),
+            levels: self.levels,
+            side_length: self.side_length,
+        }
+    }
+}
+
+// the bottom level of the serial builder, a block of 8 leaves
+fn push_leaves(data: &mut Vec<OctNode>, map: &mut OctNodeMap, leaves: [OctNode; 8]) {
+    if leaves[1..].iter().all(|l| *l == leaves[0]) {
+        data.push(leaves[0]);
+    } else if let Some(entry) = map.get_mut(&leaves) {
+        data.push(entry.node);
+        entry.count += 1;
+    } else {
+        data.extend_from_slice(&leaves);
+    }
+}
+
+// points slot at whatever got pushed from sub_start on, returns how much of it stays in data
+fn attach_child(
+    data: &mut Vec<OctNode>,
+    map: &mut OctNodeMap,
+    slot: usize,
+    sub_start: usize,
+) -> usize {
+    let len = data.len() - sub_start;
+    if len == 1 {
+        data[slot] = data[sub_start];
+        data.pop();
+        return 0;
+    }
+    let node = OctNode::new_node(sub_start as u32);
+    data[slot] = node;
+    map.insert(
+        data[sub_start..sub_start + 8].try_into().unwrap(),
+        OctNodeEntry { node, count: 1 },
+    );
+    len
+}
+
+// if none of the children added new blocks the block at i can collapse into a leaf
+// or be replaced by an identical one that's already in the map
+fn finish_group(data: &mut Vec<OctNode>, map: &mut OctNodeMap, i: usize, data_start: usize) {
+    if data_start != 0 {
+        return;
+    }
+    let first = data[i];
+    if first.is_leaf() && data[i + 1..i + 8].iter().all(|l| *l == first) {
+        data.truncate(i);
+        data.push(first);
+    } else if let Some(entry) = map.get_mut(&data[i..i + 8]) {
+        let node = entry.node;
+        entry.count += 1;
+        // the children were counted for this block, which is now being thrown away
+        for child in &data[i..i + 8] {
+            if child.is_node() {
+                let addr = child.node_data() as usize;
+                if let Some(entry) = map.get_mut(&data[addr..addr + 8]) {
+                    entry.count -= 1;
+                }
+            }
+        }
+        data.truncate(i);
+        data.push(node);
+    }
+}
+
+// positions of the subtrees from_fn_par has to build, in the order the serial builder
+// would reach them; f_node gets asked about the top levels first just like it would there
+fn collect_subtrees(
+    f_node: &impl Fn(Vector3<usize>, u32) -> Option<u32>,
+    level: u32,
+    sub_level: u32,
+    offset: Vector3<usize>,
+    jobs: &mut Vec<Vector3<usize>>,
+) {
+    for corner_offset in CORNERS {
+        let lvl = level - 1;
+        let pos = offset + corner_offset * 2usize.pow(lvl);
+        if f_node(pos, lvl).is_some() {
+            continue;
+        }
+        if lvl == sub_level {
+            jobs.push(pos);
+        } else {
+            collect_subtrees(f_node, lvl, sub_level, pos, jobs);
+        }
+    }
+}
+
+// pushes a subtree that was built on its own exactly the way the serial builder would
+// have built it in place, so blocks get deduplicated against everything that came before
+fn replay(
+    data: &mut Vec<OctNode>,
+    map: &mut OctNodeMap,
+    src: &[OctNode],
+    node: OctNode,
+    level: u32,
+) {
+    if node.is_leaf() {
+        data.push(node);
+        return;
+    }
+    let group = children(src, node);
+    if level == 1 {
+        push_leaves(data, map, group);
+        return;
+    }
+    let i = data.len();
+    data.resize(i + 8, OctNode::new_node(0));
+    let mut data_start = 0;
+    for (j, child) in group.into_iter().enumerate() {
+        if child.is_leaf() {
+            data[i + j] = child;
+        } else {
+            let sub_start = data.len();
+            replay(data, map, src, child, level - 1);
+            data_start += attach_child(data, map, i + j, sub_start);
+        }
+    }
+    finish_group(data, map, i, data_start);
+}
@@ -0,0 +1,151 @@
+use nalgebra::Vector3;
+use rustc_hash::FxHashMap;
+
+use super::{children, corner_index, find_leaf, OctNode, OctNodes, OctTree, CORNERS};
+
+// 0 is air, any other value is solid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsgOp {
+    // solid voxels of the other tree overwrite this one
+    Union,
+    // solid voxels of the other tree are carved out of this one
+    Subtract,
+    // only the parts of this tree where the other one is solid are kept
+    Intersect,
+}
+
+impl OctTree {
+    // works on whole nodes at a time, so large uniform or shared regions of either tree
+    // are handled without looking at the voxels inside of them
+    pub fn csg(&self, other: &OctTree, op: CsgOp) -> OctTree {
+        assert_eq!(self.levels, other.levels);
+        let mut csg = Csg::new(self, other, op);
+        let root = csg.combine(self.data[0], other.data[0]);
+        csg.finish(root, self)
+    }
+    // applies op with other placed at offset, everything outside of it counts as air;
+    // the parts of other that don't fit in this tree are cut off
+    pub fn stamp(&self, other: &OctTree, offset: Vector3<usize>, op: CsgOp) -> OctTree {
+        let mut csg = Csg::new(self, other, op);
+        let bounds = (offset, offset + Vector3::from_element(other.side_length));
+        let root = csg.stamp(self.data[0], self.levels, Vector3::zeros(), bounds, other);
+        csg.finish(root, self)
+    }
+}
+
+struct Csg<'a> {
+    nodes: OctNodes,
+    a: &'a [OctNode],
+    b: &'a [OctNode],
+    op: CsgOp,
+    moved_a: FxHashMap<u32, OctNode>,
+    moved_b: FxHashMap<u32, OctNode>,
+    // pairs of nodes that were already combined, so shared subtrees are only walked once
+    done: FxHashMap<(OctNode, OctNode), OctNode>,
+}
+
+impl<'a> Csg<'a> {
+    fn new(a: &'a OctTree, b: &'a OctTree, op: CsgOp) -> Self {
+        let mut nodes = OctNodes::default();
+        nodes.data.push(OctNode::new_leaf(0));
+        Self {
+            nodes,
+            a: &a.data,
+            b: &b.data,
+            op,
+            moved_a: FxHashMap::default(),
+            moved_b: FxHashMap::default(),
+            done: FxHashMap::default(),
+        }
+    }
+    fn finish(mut self, root: OctNode, tree: &OctTree) -> OctTree {
+        self.nodes.data[0] = root;
+        OctTree {
+            data: self.nodes.data.into(),
+            levels: tree.levels,
+            side_length: tree.side_length,
+        }
+    }
+    // returns an owned reference to the combination of a and b, which cover the same cube
+    fn combine(&mut self, a: OctNode, b: OctNode) -> OctNode {
+        if b.is_leaf() {
+            let solid = b.leaf_data() != 0;
+            return match (self.op, solid) {
+                (CsgOp::Union, true) => b,
+                (CsgOp::Subtract, true) | (CsgOp::Intersect, false) => OctNode::new_leaf(0),
+                (CsgOp::Union, false) | (CsgOp::Subtract, false) | (CsgOp::Intersect, true) => {
+                    self.nodes.copy_from(self.a, a, &mut self.moved_a)
+                }
+            };
+        }
+        if a.is_leaf() && a.leaf_data() == 0 {
+            return match self.op {
+                CsgOp::Union => self.nodes.copy_from(self.b, b, &mut self.moved_b),
+                CsgOp::Subtract | CsgOp::Intersect => a,
+            };
+        }
+        if let Some(&node) = self.done.get(&(a, b)) {
+            self.nodes.retain(node);
+            return node;
+        }
+        let (ac, bc) = (children(self.a, a), children(self.b, b));
+        let children = core::array::from_fn(|j| self.combine(ac[j], bc[j]));
+        let node = self.nodes.intern(children);
+        self.done.insert((a, b), node);
+        node
+    }
+    fn stamp(
+        &mut self,
+        a: OctNode,
+        level: u32,
+        pos: Vector3<usize>,
+        (min, max): (Vector3<usize>, Vector3<usize>),
+        other: &OctTree,
+    ) -> OctNode {
+        let size = 2usize.pow(level);
+        let end = pos + Vector3::from_element(size);
+        if (0..3).any(|i| end[i] <= min[i] || pos[i] >= max[i]) {
+            return self.combine(a, OctNode::new_leaf(0));
+        }
+        if (0..3).all(|i| min[i] <= pos[i] && end[i] <= max[i]) {
+            let local = pos - min;
+            let leaf = find_leaf(self.b, self.b[0], other.levels, local);
+            let leaf_end = leaf.pos + Vector3::from_element(leaf.size());
+            if (0..3).all(|i| end[i] - min[i] <= leaf_end[i]) {
+                return self.combine(a, OctNode::new_leaf(leaf.val));
+            }
+            // lined up with the nodes of the other tree, so whole subtrees can be combined
+            if local.iter().all(|p| p % size == 0) {
+                let b = node_at(self.b, self.b[0], other.levels, local, level);
+                return self.combine(a, b);
+            }
+        }
+        let ac = children(self.a, a);
+        let lvl = level - 1;
+        let children = core::array::from_fn(|j| {
+            let child_pos = pos + CORNERS[j] * 2usize.pow(lvl);
+            self.stamp(ac[j], lvl, child_pos, (min, max), other)
+        });
+        self.nodes.intern(children)
+    }
+}
+
+// the node covering the cube at pos with side 2^level, pos has to be aligned to it
+fn node_at(
+    data: &[OctNode],
+    root: OctNode,
+    levels: u32,
+    pos: Vector3<usize>,
+    level: u32,
+) -> OctNode {
+    let mut node = root;
+    let mut cur = levels;
+    let mut min = Vector3::zeros();
+    while node.is_node() && cur > level {
+        cur -= 1;
+        let corner = (pos - min).map(|p| p >> cur);
+        min += corner * 2usize.pow(cur);
+        node = children(data, node)[corner_index(corner)];
+    }
+    node
+}
@@ -0,0 +1,162 @@
+use nalgebra::Vector3;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use super::{children, OctNode, OctNodes, OctTree, CORNERS};
+
+// the cubes that have to be replaced to turn one tree into another
+#[derive(Debug, Clone)]
+pub struct OctDiff {
+    pub levels: u32,
+    pub changes: Vec<OctChange>,
+}
+
+#[derive(Debug, Clone)]
+pub enum OctChange {
+    // the cube at pos with side 2^level becomes val
+    Fill {
+        pos: Vector3<usize>,
+        level: u32,
+        val: u32,
+    },
+    // the cube at pos gets replaced by tree, pos is a multiple of its side length
+    Replace {
+        pos: Vector3<usize>,
+        tree: OctTree,
+    },
+}
+
+impl OctDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl OctTree {
+    // only walks into nodes that differ; when new was built from old through an
+    // OctTreeBuilder (without compacting) unchanged groups keep their address and
+    // are skipped right away, otherwise groups get compared by content
+    pub fn diff(old: &OctTree, new: &OctTree) -> OctDiff {
+        assert_eq!(old.levels, new.levels);
+        let prefix =
+            new.data.len() >= old.data.len() && new.data[1..old.data.len()] == old.data[1..];
+        let mut differ = Differ {
+            old: &old.data,
+            new: &new.data,
+            shared: if prefix { old.data.len() } else { 0 },
+            equal: FxHashMap::default(),
+            changes: Vec::new(),
+        };
+        differ.diff(old.data[0], new.data[0], old.levels, Vector3::zeros());
+        OctDiff {
+            levels: old.levels,
+            changes: differ.changes,
+        }
+    }
+    pub fn apply(&self, diff: &OctDiff) -> OctTree {
+        let mut builder = self.to_builder();
+        builder.apply(diff);
+        builder.build()
+    }
+}
+
+struct Differ<'a> {
+    old: &'a [OctNode],
+    new: &'a [OctNode],
+    // groups below this address are the same in both arrays
+    shared: usize,
+    equal: FxHashMap<(OctNode, OctNode), bool>,
+    changes: Vec<OctChange>,
+}
+
+impl Differ<'_> {
+    fn diff(&mut self, a: OctNode, b: OctNode, level: u32, pos: Vector3<usize>) {
+        if self.equal(a, b) {
+            return;
+        }
+        if b.is_leaf() {
+            self.changes.push(OctChange::Fill {
+                pos,
+                level,
+                val: b.leaf_data(),
+            });
+            return;
+        }
+        let (ac, bc) = (children(self.old, a), children(self.new, b));
+        let start = self.changes.len();
+        let lvl = level - 1;
+        for (j, corner_offset) in CORNERS.iter().enumerate() {
+            if !self.equal(ac[j], bc[j]) {
+                self.diff(ac[j], bc[j], lvl, pos + corner_offset * 2usize.pow(lvl));
+            }
+        }
+        // when most of the node changed, the whole new subtree can be smaller than
+        // all the changes in it
+        if self.changes.len() - start > 1 {
+            let cost = self.changes[start..].iter().map(cost).sum::<usize>();
+            if self.smaller_than(b, cost - REPLACE_COST) {
+                self.changes.truncate(start);
+                self.changes.push(OctChange::Replace {
+                    pos,
+                    tree: subtree(self.new, b, level),
+                });
+            }
+        }
+    }
+    // whether the subtree at node has fewer than limit nodes once it's copied out,
+    // only walks as far as it has to
+    fn smaller_than(&self, node: OctNode, limit: usize) -> bool {
+        let mut seen = FxHashSet::default();
+        let mut stack = vec![node];
+        let mut len = 1;
+        while let Some(node) = stack.pop() {
+            if node.is_leaf() || !seen.insert(node) {
+                continue;
+            }
+            len += 8;
+            if len >= limit {
+                return false;
+            }
+            stack.extend(children(self.new, node));
+        }
+        true
+    }
+    // trees never have a group of 8 equal leaves, so a leaf and a group always differ
+    fn equal(&mut self, a: OctNode, b: OctNode) -> bool {
+        if a.is_leaf() || b.is_leaf() {
+            return a == b;
+        }
+        if a == b && (a.node_data() as usize) < self.shared {
+            return true;
+        }
+        if let Some(equal) = self.equal.get(&(a, b)) {
+            return *equal;
+        }
+        let (ac, bc) = (children(self.old, a), children(self.new, b));
+        let equal = (0..8).all(|j| self.equal(ac[j], bc[j]));
+        self.equal.insert((a, b), equal);
+        equal
+    }
+}
+
+// roughly how many u32s a change takes up, without the nodes of a replacing tree
+const FILL_COST: usize = 5;
+const REPLACE_COST: usize = 4;
+
+fn cost(change: &OctChange) -> usize {
+    match change {
+        OctChange::Fill { .. } => FILL_COST,
+        OctChange::Replace { tree, .. } => REPLACE_COST + tree.data.len(),
+    }
+}
+
+fn subtree(data: &[OctNode], node: OctNode, level: u32) -> OctTree {
+    let mut nodes = OctNodes::default();
+    nodes.data.push(node);
+    let mut moved = FxHashMap::default();
+    nodes.data[0] = nodes.copy_from(data, node, &mut moved);
+    OctTree {
+        data: nodes.data.into(),
+        levels: level,
+        side_length: 2usize.pow(level),
+    }
+}
@@ -0,0 +1,247 @@
+mod builder;
+mod csg;
+mod diff;
+mod nodes;
+mod raycast;
+mod serialize;
+mod shape;
+mod stats;
+mod store;
+#[cfg(test)]
+mod tests;
+pub use builder::*;
+pub use csg::*;
+pub use diff::*;
+pub use raycast::*;
+pub use serialize::*;
+pub use shape::*;
+pub use stats::*;
+pub use store::*;
+
+use nodes::{children, OctNodeEntry, OctNodeMap, OctNodes};
+
+use std::{fmt::Debug, hash::Hash, sync::Arc};
+
+use nalgebra::Vector3;
+use ndarray::ArrayView3;
+
+const LEAF_BIT: u32 = 1 << 31;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct OctNode(u32);
+impl OctNode {
+    pub const fn new_node(addr: u32) -> Self {
+        Self(addr)
+    }
+    pub const fn new_leaf(data: u32) -> Self {
+        Self(data | LEAF_BIT)
+    }
+    pub const fn is_leaf(&self) -> bool {
+        self.0 >= LEAF_BIT
+    }
+    pub const fn is_node(&self) -> bool {
+        self.0 < LEAF_BIT
+    }
+    pub const fn node_data(&self) -> u32 {
+        self.0
+    }
+    pub const fn leaf_data(&self) -> u32 {
+        self.0 & !LEAF_BIT
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OctTree {
+    data: Arc<[OctNode]>,
+    levels: u32,
+    side_length: usize,
+}
+
+const CORNERS: [Vector3<usize>; 8] = [
+    Vector3::new(0, 0, 0),
+    Vector3::new(0, 0, 1),
+    Vector3::new(0, 1, 0),
+    Vector3::new(0, 1, 1),
+    Vector3::new(1, 0, 0),
+    Vector3::new(1, 0, 1),
+    Vector3::new(1, 1, 0),
+    Vector3::new(1, 1, 1),
+];
+
+impl OctTree {
+    pub fn from_leaf(val: u32, levels: u32) -> Self {
+        OctTreeBuilder::from_leaf(val, levels).build()
+    }
+    pub fn from_leaf_fn(f_leaf: &mut impl FnMut(Vector3<usize>) -> u32, levels: u32) -> Self {
+        OctTreeBuilder::from_leaf_fn(f_leaf, levels).build()
+    }
+    pub fn from_fn(
+        f_leaf: &mut impl FnMut(Vector3<usize>) -> u32,
+        f_node: &mut impl FnMut(Vector3<usize>, u32) -> Option<u32>,
+        levels: u32,
+    ) -> Self {
+        OctTreeBuilder::from_fn(f_leaf, f_node, levels).build()
+    }
+    pub fn from_fn_offset(
+        f_leaf: &mut impl FnMut(Vector3<usize>) -> u32,
+        f_node: &mut impl FnMut(Vector3<usize>, u32) -> Option<u32>,
+        levels: u32,
+        offset: Vector3<usize>,
+    ) -> Self {
+        OctTreeBuilder::from_fn_offset(f_leaf, f_node, levels, offset).build()
+    }
+    pub fn from_fn_par(
+        f_leaf: &(impl Fn(Vector3<usize>) -> u32 + Sync),
+        f_node: &(impl Fn(Vector3<usize>, u32) -> Option<u32> + Sync),
+        levels: u32,
+        split: u32,
+    ) -> Self {
+        OctTreeBuilder::from_fn_par(f_leaf, f_node, levels, split).build()
+    }
+    pub fn from_arr(arr: ArrayView3<u32>, levels: u32) -> Self {
+        OctTreeBuilder::from_arr(arr, levels).build()
+    }
+    pub fn to_builder(&self) -> OctTreeBuilder {
+        OctTreeBuilder::from_tree(self)
+    }
+    pub fn get(&self, pos: Vector3<usize>) -> u32 {
+        find_leaf(&self.data, self.data[0], self.levels, pos).val
+    }
+    pub fn leaf_at(&self, pos: Vector3<usize>) -> OctLeaf {
+        find_leaf(&self.data, self.data[0], self.levels, pos)
+    }
+    pub fn raw(&self) -> &[OctNode] {
+        &self.data
+    }
+    pub fn levels(&self) -> u32 {
+        self.levels
+    }
+}
+
+pub struct OctTreeIter<'a> {
+    queue: Vec<(OctNode, u32)>,
+    cur: u32,
+    run: usize,
+    data: &'a [OctNode],
+}
+
+impl<'a> Iterator for OctTreeIter<'a> {
+    type Item = u32;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.run != 0 {
+            self.run -= 1;
+            return Some(self.cur);
+        }
+        let (node, level) = self.queue.pop()?;
+        if node.is_leaf() {
+            self.run = 8usize.pow(level);
+            self.cur = node.leaf_data();
+        } else {
+            let addr = node.node_data() as usize;
+            let add = &self.data[addr..addr + 8];
+            self.queue.extend(add.iter().rev().map(|n| (*n, level - 1)));
+        }
+        self.next()
+    }
+}
+
+impl<'a> IntoIterator for &'a OctTree {
+    type Item = u32;
+    type IntoIter = OctTreeIter<'a>;
+    fn into_iter(self) -> Self::IntoIter {
+        OctTreeIter {
+            data: &self.data,
+            cur: 0,
+            run: 0,
+            queue: vec![(self.data[0], self.levels)],
+        }
+    }
+}
+
+// a uniform cube of the tree, with side length 2^level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OctLeaf {
+    pub pos: Vector3<usize>,
+    pub level: u32,
+    pub val: u32,
+}
+
+impl OctLeaf {
+    pub fn size(&self) -> usize {
+        2usize.pow(self.level)
+    }
+}
+
+pub struct OctTreeLeafIter<'a> {
+    stack: Vec<(OctNode, u32, Vector3<usize>)>,
+    bounds: Option<(Vector3<usize>, Vector3<usize>)>,
+    data: &'a [OctNode],
+}
+
+impl<'a> Iterator for OctTreeLeafIter<'a> {
+    type Item = OctLeaf;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, level, pos) = self.stack.pop()?;
+            if let Some((min, max)) = self.bounds {
+                let end = pos + Vector3::from_element(2usize.pow(level));
+                if (0..3).any(|i| end[i] <= min[i] || pos[i] >= max[i]) {
+                    continue;
+                }
+            }
+            if node.is_leaf() {
+                return Some(OctLeaf {
+                    pos,
+                    level,
+                    val: node.leaf_data(),
+                });
+            }
+            let addr = node.node_data() as usize;
+            let lvl = level - 1;
+            for (j, corner_offset) in CORNERS.iter().enumerate().rev() {
+                let child_pos = pos + corner_offset * 2usize.pow(lvl);
+                self.stack.push((self.data[addr + j], lvl, child_pos));
+            }
+        }
+    }
+}
+
+impl OctTree {
+    pub fn leaves(&self) -> OctTreeLeafIter<'_> {
+        OctTreeLeafIter {
+            stack: vec![(self.data[0], self.levels, Vector3::from_element(0))],
+            bounds: None,
+            data: &self.data,
+        }
+    }
+    // only leaves that overlap the box are visited, but they're returned whole;
+    // min is inclusive and max is exclusive
+    pub fn leaves_in(&self, min: Vector3<usize>, max: Vector3<usize>) -> OctTreeLeafIter<'_> {
+        OctTreeLeafIter {
+            bounds: Some((min, max)),
+            ..self.leaves()
+        }
+    }
+}
+
+fn find_leaf(data: &[OctNode], root: OctNode, levels: u32, pos: Vector3<usize>) -> OctLeaf {
+    let mut node = root;
+    let mut level = levels;
+    let mut min = Vector3::zeros();
+    while node.is_node() {
+        level -= 1;
+        let corner = (pos - min).map(|p| p >> level);
+        min += corner * 2usize.pow(level);
+        node = children(data, node)[corner_index(corner)];
+    }
+    OctLeaf {
+        pos: min,
+        level,
+        val: node.leaf_data(),
+    }
+}
+
+fn corner_index(corner: Vector3<usize>) -> usize {
+    corner.x * 4 + corner.y * 2 + corner.z
+}
@@ -0,0 +1,171 @@
+use rustc_hash::FxHashMap;
+
+use super::OctNode;
+
+// the children of a leaf are the leaf itself
+pub(super) fn children(data: &[OctNode], node: OctNode) -> [OctNode; 8] {
+    if node.is_leaf() {
+        return [node; 8];
+    }
+    let addr = node.node_data() as usize;
+    data[addr..addr + 8].try_into().unwrap()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct OctNodeEntry {
+    pub node: OctNode,
+    pub count: u32,
+}
+
+pub(super) type OctNodeMap = FxHashMap<[OctNode; 8], OctNodeEntry>;
+
+// groups of 8 children in a flat array, deduplicated through the map and reference counted
+// by how many nodes point at them; unreferenced groups stay in data as garbage
+#[derive(Debug, Clone, Default)]
+pub(super) struct OctNodes {
+    pub data: Vec<OctNode>,
+    pub map: OctNodeMap,
+    pub garbage: usize,
+}
+
+impl OctNodes {
+    // checks that every reachable group of children is in bounds, that there are no loops and
+    // that the tree bottoms out within levels, then rebuilds the dedup map and garbage count.
+    // groups of leaves get shared between levels so a group can show up at different depths
+    pub fn from_raw(data: Vec<OctNode>, levels: u32) -> Option<Self> {
+        if data.is_empty() || levels == 0 || levels > 31 {
+            return None;
+        }
+        // depth of each group below it, None while its children are still being checked
+        let mut depths = FxHashMap::<u32, Option<u32>>::default();
+        let mut stack = vec![(data[0], false)];
+        while let Some((node, done)) = stack.pop() {
+            if node.is_leaf() {
+                continue;
+            }
+            let addr = node.node_data() as usize;
+            if addr == 0 || addr + 8 > data.len() {
+                return None;
+            }
+            let group = children(&data, node);
+            if done {
+                let depth = group
+                    .iter()
+                    .filter(|c| c.is_node())
+                    .map(|c| depths[&c.node_data()].unwrap_or(0))
+                    .max()
+                    .unwrap_or(0)
+                    + 1;
+                if depth > levels {
+                    return None;
+                }
+                depths.insert(node.node_data(), Some(depth));
+                continue;
+            }
+            match depths.get(&node.node_data()) {
+                Some(Some(_)) => continue,
+                Some(None) => return None,
+                None => {}
+            }
+            depths.insert(node.node_data(), None);
+            stack.push((node, true));
+            stack.extend(group.map(|c| (c, false)));
+        }
+        let mut map = OctNodeMap::default();
+        let count = |map: &mut OctNodeMap, node: OctNode| {
+            map.entry(children(&data, node))
+                .or_insert(OctNodeEntry { node, count: 0 })
+                .count += 1;
+        };
+        if data[0].is_node() {
+            count(&mut map, data[0]);
+        }
+        for addr in depths.keys() {
+            for child in children(&data, OctNode::new_node(*addr)) {
+                if child.is_node() {
+                    count(&mut map, child);
+                }
+            }
+        }
+        let garbage = (data.len() - 1).checked_sub(map.len() * 8)?;
+        Some(Self { data, map, garbage })
+    }
+    pub fn children(&self, node: OctNode) -> [OctNode; 8] {
+        children(&self.data, node)
+    }
+    // takes ownership of a reference to each child and returns an owned reference
+    pub fn intern(&mut self, children: [OctNode; 8]) -> OctNode {
+        let first = children[0];
+        if first.is_leaf() && children[1..].iter().all(|c| *c == first) {
+            return first;
+        }
+        self.intern_group(children)
+    }
+    // same as intern but never collapses uniform leaves, for when a group is required
+    pub fn intern_group(&mut self, children: [OctNode; 8]) -> OctNode {
+        if let Some(entry) = self.map.get_mut(&children) {
+            entry.count += 1;
+            let node = entry.node;
+            for child in children {
+                self.release(child);
+            }
+            return node;
+        }
+        let node = OctNode::new_node(self.data.len() as u32);
+        self.data.extend_from_slice(&children);
+        self.map.insert(children, OctNodeEntry { node, count: 1 });
+        node
+    }
+    pub fn retain(&mut self, node: OctNode) {
+        if node.is_leaf() {
+            return;
+        }
+        let children = self.children(node);
+        if let Some(entry) = self.map.get_mut(&children) {
+            entry.count += 1;
+        }
+    }
+    // drops a reference; once nothing points at a group of children it's removed
+    // from the map so it can't be reused, and its own children get released
+    pub fn release(&mut self, node: OctNode) {
+        if node.is_leaf() {
+            return;
+        }
+        let children = self.children(node);
+        if let Some(entry) = self.map.get_mut(&children) {
+            entry.count -= 1;
+            if entry.count > 0 {
+                return;
+            }
+            self.map.remove(&children);
+        }
+        self.garbage += 8;
+        for child in children {
+            self.release(child);
+        }
+    }
+    // copies the subtree at node out of another node array, returns an owned reference;
+    // moved remembers groups that were already copied so shared subtrees are only walked once
+    pub fn copy_from(
+        &mut self,
+        old: &[OctNode],
+        node: OctNode,
+        moved: &mut FxHashMap<u32, OctNode>,
+    ) -> OctNode {
+        if node.is_leaf() {
+            return node;
+        }
+        if let Some(&new) = moved.get(&node.node_data()) {
+            self.retain(new);
+            return new;
+        }
+        let addr = node.node_data() as usize;
+        let mut children: [OctNode; 8] = old[addr..addr + 8].try_into().unwrap();
+        for child in &mut children {
+            *child = self.copy_from(old, *child, moved);
+        }
+        let new = self.intern(children);
+        moved.insert(node.node_data(), new);
+        new
+    }
+}
@@ -0,0 +1,284 @@
+use nalgebra::Vector3;
+
+use super::OctTree;
+
+// same idea as cast_ray in ray_oct/shader/compute.wgsl: walk the uniform leaves along the
+// ray. The shader collects every hit until they add up to an opaque color, this stops at
+// the first leaf that stop says is solid, so passing the shader's opaque ids gives the
+// voxel the player sees behind water and such
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub t: f32,
+    pub pos: Vector3<usize>,
+    // axis of the face the ray entered through; normal points back out of it
+    pub axis: usize,
+    pub normal: Vector3<i32>,
+    pub id: u32,
+}
+
+impl OctTree {
+    // origin and dir are in voxel units relative to the tree's min corner,
+    // dir doesn't have to be normalized but t is measured in multiples of it
+    pub fn raycast(
+        &self,
+        origin: Vector3<f32>,
+        dir: Vector3<f32>,
+        max_t: f32,
+        stop: impl Fn(u32) -> bool,
+    ) -> Option<RayHit> {
+        // a ray that doesn't go anywhere would never reach max_t
+        if dir == Vector3::zeros() || dir.iter().any(|d| !d.is_finite()) {
+            return None;
+        }
+        let side_len = self.side_length as f32;
+        let dir_sign = dir.map(|d| if d < 0.0 { -1 } else { 1 });
+
+        // find where ray intersects with the tree
+        let mut t_start = f32::NEG_INFINITY;
+        let mut t_end = f32::INFINITY;
+        let mut axis = 0;
+        for i in 0..3 {
+            if dir[i] == 0.0 {
+                if origin[i] < 0.0 || origin[i] >= side_len {
+                    return None;
+                }
+                continue;
+            }
+            let (near, far) = if dir[i] > 0.0 {
+                (0.0, side_len)
+            } else {
+                (side_len, 0.0)
+            };
+            let t_near = (near - origin[i]) / dir[i];
+            let t_far = (far - origin[i]) / dir[i];
+            if t_near > t_start {
+                t_start = t_near;
+                axis = i;
+            }
+            t_end = t_end.min(t_far);
+        }
+        if t_end < t_start || t_end < 0.0 {
+            return None;
+        }
+        let mut t = t_start.max(0.0);
+        let entered = (t_start > 0.0).then(|| {
+            let face = if dir[axis] > 0.0 { 0 } else { self.side_length };
+            (axis, face)
+        });
+        let mut vox = self.voxel_at(origin + dir * t, dir_sign, entered);
+
+        loop {
+            if t > max_t {
+                return None;
+            }
+            let leaf = self.leaf_at(vox);
+            if stop(leaf.val) {
+                let mut normal = Vector3::zeros();
+                normal[axis] = -dir_sign[axis];
+                return Some(RayHit {
+                    t,
+                    pos: vox,
+                    axis,
+                    normal,
+                    id: leaf.val,
+                });
+            }
+
+            // move to the closest face of the leaf in the direction of the ray
+            let size = leaf.size();
+            let mut t_next = f32::INFINITY;
+            let mut next_face = 0;
+            for i in 0..3 {
+                if dir[i] == 0.0 {
+                    continue;
+                }
+                let face = if dir[i] > 0.0 {
+                    leaf.pos[i] + size
+                } else {
+                    leaf.pos[i]
+                };
+                let t_face = (face as f32 - origin[i]) / dir[i];
+                if t_face < t_next {
+                    t_next = t_face;
+                    axis = i;
+                    next_face = face;
+                }
+            }
+            if (dir[axis] > 0.0 && next_face >= self.side_length)
+                || (dir[axis] < 0.0 && next_face == 0)
+            {
+                return None;
+            }
+            t = t_next.max(t);
+            vox = self.voxel_at(origin + dir * t, dir_sign, Some((axis, next_face)));
+        }
+    }
+
+    // the voxel the ray is in at a point; the axis that was just crossed is snapped to the
+    // face so float error can't put it back in the previous leaf
+    fn voxel_at(
+        &self,
+        point: Vector3<f32>,
+        dir_sign: Vector3<i32>,
+        crossed: Option<(usize, usize)>,
+    ) -> Vector3<usize> {
+        let max = (self.side_length - 1) as f32;
+        let mut vox = point.map(|p| p.floor().clamp(0.0, max) as usize);
+        if let Some((axis, face)) = crossed {
+            vox[axis] = if dir_sign[axis] > 0 { face } else { face - 1 };
+        }
+        vox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+    use ndarray::Array3;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    // steps through every voxel along the ray and looks each one up with get
+    fn dda(
+        tree: &OctTree,
+        origin: Vector3<f32>,
+        dir: Vector3<f32>,
+        max_t: f32,
+        stop: fn(u32) -> bool,
+    ) -> Option<RayHit> {
+        let n = tree.side_length;
+        let mut t_start = 0.0f32;
+        let mut t_end = f32::INFINITY;
+        let mut entered = None;
+        for i in 0..3 {
+            if dir[i] == 0.0 {
+                if origin[i] < 0.0 || origin[i] >= n as f32 {
+                    return None;
+                }
+                continue;
+            }
+            let a = -origin[i] / dir[i];
+            let b = (n as f32 - origin[i]) / dir[i];
+            if a.min(b) > t_start {
+                t_start = a.min(b);
+                entered = Some(i);
+            }
+            t_end = t_end.min(a.max(b));
+        }
+        if t_end < t_start {
+            return None;
+        }
+        let step = dir.map(|d| if d < 0.0 { -1 } else { 1 });
+        let point = origin + dir * t_start;
+        let mut vox = point.map(|p| (p.floor() as i64).clamp(0, n as i64 - 1));
+        let mut axis = entered.unwrap_or(0);
+        if let Some(i) = entered {
+            vox[i] = if dir[i] > 0.0 { 0 } else { n as i64 - 1 };
+        }
+        let mut t = t_start;
+        loop {
+            if t > max_t || vox.iter().any(|v| *v < 0 || *v >= n as i64) {
+                return None;
+            }
+            let pos = vox.map(|v| v as usize);
+            let id = tree.get(pos);
+            if stop(id) {
+                let mut normal = Vector3::zeros();
+                normal[axis] = -step[axis];
+                return Some(RayHit {
+                    t,
+                    pos,
+                    axis,
+                    normal,
+                    id,
+                });
+            }
+            let mut t_next = f32::INFINITY;
+            for i in 0..3 {
+                if dir[i] == 0.0 {
+                    continue;
+                }
+                let face = if dir[i] > 0.0 { vox[i] + 1 } else { vox[i] };
+                let t_face = (face as f32 - origin[i]) / dir[i];
+                if t_face < t_next {
+                    t_next = t_face;
+                    axis = i;
+                }
+            }
+            t = t_next.max(t);
+            vox[axis] += step[axis] as i64;
+        }
+    }
+
+    fn random_ray(rng: &mut StdRng, n: f32) -> (Vector3<f32>, Vector3<f32>) {
+        let origin = Vector3::from_fn(|_, _| rng.gen_range(-n * 0.5..n * 1.5));
+        let mut dir = Vector3::from_fn(|_, _| rng.gen_range(-1.0..1.0));
+        // axis aligned rays and rays in a plane are the easiest to get wrong
+        for i in 0..3 {
+            if rng.gen_bool(0.2) {
+                dir[i] = 0.0;
+            }
+        }
+        if dir == Vector3::zeros() {
+            dir[rng.gen_range(0..3)] = -1.0;
+        }
+        (origin, dir)
+    }
+
+    #[test]
+    fn matches_dda() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for levels in 1..=5 {
+            let n = 2usize.pow(levels);
+            for density in [0.01, 0.1, 0.5] {
+                let arr = Array3::from_shape_fn((n, n, n), |_| {
+                    rng.gen_bool(density) as u32 * rng.gen_range(1..4)
+                });
+                let tree = OctTree::from_arr(arr.view(), levels);
+                for _ in 0..2000 {
+                    let (origin, dir) = random_ray(&mut rng, n as f32);
+                    let max_t = if rng.gen_bool(0.5) {
+                        f32::INFINITY
+                    } else {
+                        rng.gen_range(0.0..2.0 * n as f32)
+                    };
+                    // 3 is see-through for the second one, like water in the shader
+                    let stop: fn(u32) -> bool = if rng.gen_bool(0.5) {
+                        |id| id != 0
+                    } else {
+                        |id| id != 0 && id != 3
+                    };
+                    let hit = tree.raycast(origin, dir, max_t, stop);
+                    let expected = dda(&tree, origin, dir, max_t, stop);
+                    let msg = format!("origin {origin:?} dir {dir:?} max_t {max_t}");
+                    match (hit, expected) {
+                        (Some(hit), Some(expected)) => {
+                            assert_eq!(hit.pos, expected.pos, "{msg}");
+                            assert_eq!(hit.id, expected.id, "{msg}");
+                            assert!((hit.t - expected.t).abs() <= 1e-3 * hit.t.max(1.0), "{msg}");
+                            // starting inside a solid voxel has no face to hit
+                            if hit.t > 0.0 {
+                                assert_eq!(hit.axis, expected.axis, "{msg}");
+                                assert_eq!(hit.normal, expected.normal, "{msg}");
+                            }
+                        }
+                        (hit, expected) => assert_eq!(hit, expected, "{msg}"),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn zero_dir() {
+        let air = OctTree::from_leaf(0, 3);
+        let solid = OctTree::from_leaf(1, 3);
+        for tree in [air, solid] {
+            let origin = Vector3::from_element(4.5);
+            let stop = |id| id != 0;
+            assert_eq!(tree.raycast(origin, Vector3::zeros(), f32::INFINITY, stop), None);
+            assert_eq!(tree.raycast(origin, Vector3::zeros(), 10.0, stop), None);
+        }
+    }
+}
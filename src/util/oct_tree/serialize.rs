@@ -0,0 +1,331 @@
+// Binary format for OctTree, used for saves and for sending chunks over the wire.
+// Everything is little endian.
+//
+// header (16 bytes)
+//   magic   [u8; 4]  b"OCTR"
+//   version u16      FORMAT_VERSION
+//   flags   u16      bit 0 set = payload is run length encoded
+//   levels  u32
+//   len     u32      number of nodes in the node array
+// payload
+//   raw:    len nodes as u32
+//   rle:    tokens until len nodes have been produced, each starting with a u32 header;
+//           if the top bit is set, the next node is repeated (header & !RUN_BIT) times,
+//           at most MAX_RUN, otherwise the header is the number of literal nodes that follow
+// footer
+//   crc32 (IEEE) of the header and payload: u32
+//
+// The node array is stored exactly as it is in memory, so a decoded tree has the same
+// raw() as the one that was encoded. The dedup map isn't stored; it's rebuilt on decode.
+// Decoding never trusts the header: levels has to be one a tree can have, len has to fit
+// in that many levels and runs are capped, so what gets allocated is bounded by the size
+// of the payload.
+
+use super::{OctNode, OctNodes, OctTree, LEAF_BIT};
+
+pub const MAGIC: [u8; 4] = *b"OCTR";
+pub const FORMAT_VERSION: u16 = 1;
+const HEADER_LEN: usize = 16;
+const FLAG_RLE: u16 = 1;
+const RUN_BIT: u32 = 1 << 31;
+// shorter runs are cheaper to store as literals
+const MIN_RUN: usize = 3;
+// longer runs are split, so a few bytes can't decode into a huge node array
+const MAX_RUN: usize = 64;
+// same as OctNodes::from_raw accepts
+const MAX_LEVELS: u32 = 31;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Rle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u16),
+    Checksum,
+    InvalidTree,
+    UnexpectedLevels(u32),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "oct tree data ended early"),
+            Self::BadMagic => write!(f, "not oct tree data"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported oct tree format version {v}"),
+            Self::Checksum => write!(f, "oct tree checksum mismatch"),
+            Self::InvalidTree => write!(f, "oct tree node data is malformed"),
+            Self::UnexpectedLevels(l) => write!(f, "oct tree has an unexpected {l} levels"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl OctTree {
+    pub fn encode(&self, compression: Compression) -> Vec<u8> {
+        let flags = match compression {
+            Compression::None => 0,
+            Compression::Rle => FLAG_RLE,
+        };
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.data.len() * 4 + 4);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&flags.to_le_bytes());
+        bytes.extend_from_slice(&self.levels.to_le_bytes());
+        bytes.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        match compression {
+            Compression::None => {
+                for node in self.data.iter() {
+                    bytes.extend_from_slice(&node.0.to_le_bytes());
+                }
+            }
+            Compression::Rle => {
+                let mut literal_start = 0;
+                let mut i = 0;
+                for run in self.data.chunk_by(|a, b| a == b) {
+                    if run.len() >= MIN_RUN {
+                        push_literals(&mut bytes, &self.data[literal_start..i]);
+                        for part in run.chunks(MAX_RUN) {
+                            bytes.extend_from_slice(&(part.len() as u32 | RUN_BIT).to_le_bytes());
+                            bytes.extend_from_slice(&part[0].0.to_le_bytes());
+                        }
+                        literal_start = i + run.len();
+                    }
+                    i += run.len();
+                }
+                push_literals(&mut bytes, &self.data[literal_start..]);
+            }
+        }
+        let crc = crc32(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode_inner(bytes, None)
+    }
+
+    // like decode, but only accepts trees with the given number of levels, which is
+    // checked before the nodes are read
+    pub fn decode_levels(bytes: &[u8], levels: u32) -> Result<Self, DecodeError> {
+        Self::decode_inner(bytes, Some(levels))
+    }
+
+    fn decode_inner(bytes: &[u8], expected_levels: Option<u32>) -> Result<Self, DecodeError> {
+        if bytes.len() < HEADER_LEN + 4 {
+            return Err(DecodeError::Truncated);
+        }
+        if bytes[0..4] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != FORMAT_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let (body, crc) = bytes.split_at(bytes.len() - 4);
+        if crc32(body) != read_u32(crc, 0) {
+            return Err(DecodeError::Checksum);
+        }
+        let flags = u16::from_le_bytes([body[6], body[7]]);
+        let levels = read_u32(body, 8);
+        let len = read_u32(body, 12) as usize;
+        if expected_levels.is_some_and(|l| l != levels) {
+            return Err(DecodeError::UnexpectedLevels(levels));
+        }
+        // levels and len come from the data, so they're checked before anything gets
+        // allocated for them
+        if levels == 0 || levels > MAX_LEVELS || len > max_len(levels) {
+            return Err(DecodeError::InvalidTree);
+        }
+        let payload = &body[HEADER_LEN..];
+        if payload.len() % 4 != 0 {
+            return Err(DecodeError::Truncated);
+        }
+        let words = payload.chunks_exact(4).map(|w| read_u32(w, 0));
+        let data: Vec<OctNode> = if flags & FLAG_RLE != 0 {
+            let mut data = Vec::new();
+            let mut words = words;
+            while let Some(header) = words.next() {
+                let count = (header & !RUN_BIT) as usize;
+                if data.len() + count > len {
+                    return Err(DecodeError::InvalidTree);
+                }
+                if header & RUN_BIT != 0 {
+                    if count > MAX_RUN {
+                        return Err(DecodeError::InvalidTree);
+                    }
+                    let node = words.next().ok_or(DecodeError::Truncated)?;
+                    data.resize(data.len() + count, OctNode(node));
+                } else {
+                    if count > words.len() {
+                        return Err(DecodeError::Truncated);
+                    }
+                    data.extend(words.by_ref().take(count).map(OctNode));
+                }
+            }
+            data
+        } else {
+            if payload.len() / 4 != len {
+                return Err(DecodeError::Truncated);
+            }
+            words.map(OctNode).collect()
+        };
+        if data.len() != len {
+            return Err(DecodeError::Truncated);
+        }
+        let nodes = OctNodes::from_raw(data, levels).ok_or(DecodeError::InvalidTree)?;
+        Ok(Self {
+            data: nodes.data.into(),
+            side_length: 2usize.pow(levels),
+            levels,
+        })
+    }
+}
+
+// the most nodes a tree with this many levels can have, with no two groups alike,
+// and never more than a node can point at
+fn max_len(levels: u32) -> usize {
+    (0..=levels)
+        .try_fold(0usize, |sum, l| sum.checked_add(8usize.checked_pow(l)?))
+        .unwrap_or(usize::MAX)
+        .min(LEAF_BIT as usize)
+}
+
+fn push_literals(bytes: &mut Vec<u8>, nodes: &[OctNode]) {
+    if nodes.is_empty() {
+        return;
+    }
+    bytes.extend_from_slice(&(nodes.len() as u32).to_le_bytes());
+    for node in nodes {
+        bytes.extend_from_slice(&node.0.to_le_bytes());
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |c, b| {
+        CRC_TABLE[((c ^ *b as u32) & 0xFF) as usize] ^ (c >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array3;
+
+    use super::*;
+
+    fn with_header(levels: u32, len: u32, flags: u16, payload: &[u32]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&flags.to_le_bytes());
+        bytes.extend_from_slice(&levels.to_le_bytes());
+        bytes.extend_from_slice(&len.to_le_bytes());
+        for word in payload {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        let crc = crc32(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let arr = Array3::from_shape_fn((16, 16, 16), |(x, y, z)| ((x ^ y) & z) as u32 % 3);
+        let tree = OctTree::from_arr(arr.view(), 4);
+        for compression in [Compression::None, Compression::Rle] {
+            let decoded = OctTree::decode(&tree.encode(compression)).unwrap();
+            assert_eq!(decoded.raw(), tree.raw());
+            assert_eq!(decoded.levels, tree.levels);
+        }
+    }
+
+    #[test]
+    fn long_runs_round_trip() {
+        // left over groups from edits are the only place long runs show up
+        let mut data = vec![OctNode::new_leaf(1)];
+        data.extend([OctNode::new_leaf(2); 8 * 25]);
+        let tree = OctTree {
+            data: data.into(),
+            side_length: 8,
+            levels: 3,
+        };
+        let encoded = tree.encode(Compression::Rle);
+        assert_eq!(OctTree::decode(&encoded).unwrap().raw(), tree.raw());
+    }
+
+    #[test]
+    fn len_limit() {
+        assert_eq!(max_len(1), 9);
+        assert_eq!(max_len(2), 73);
+        assert_eq!(max_len(31), LEAF_BIT as usize);
+        assert_eq!(max_len(u32::MAX), LEAF_BIT as usize);
+    }
+
+    #[test]
+    fn huge_len_is_rejected() {
+        let leaf = OctNode::new_leaf(1).0;
+        // a single run claiming far more nodes than a two level tree can have
+        let bytes = with_header(2, u32::MAX, FLAG_RLE, &[(RUN_BIT - 1) | RUN_BIT, leaf]);
+        assert_eq!(OctTree::decode(&bytes).err(), Some(DecodeError::InvalidTree));
+        let bytes = with_header(2, 74, 0, &[leaf]);
+        assert_eq!(OctTree::decode(&bytes).err(), Some(DecodeError::InvalidTree));
+        // within the limit but not backed by the payload
+        let bytes = with_header(2, 73, 0, &[leaf]);
+        assert_eq!(OctTree::decode(&bytes).err(), Some(DecodeError::Truncated));
+        // runs can't produce more nodes than the header says
+        let bytes = with_header(2, 8, FLAG_RLE, &[9 | RUN_BIT, leaf]);
+        assert_eq!(OctTree::decode(&bytes).err(), Some(DecodeError::InvalidTree));
+        // literals can't claim more nodes than the payload holds
+        let bytes = with_header(2, 9, FLAG_RLE, &[9, leaf]);
+        assert_eq!(OctTree::decode(&bytes).err(), Some(DecodeError::Truncated));
+    }
+
+    #[test]
+    fn untrusted_levels() {
+        let leaf = OctNode::new_leaf(1).0;
+        // 28 bytes asking for a 31 level tree made of one run of 2^31 - 1 nodes,
+        // this used to try allocating 8 GiB
+        let bytes = with_header(31, RUN_BIT - 1, FLAG_RLE, &[(RUN_BIT - 1) | RUN_BIT, leaf]);
+        assert_eq!(bytes.len(), 28);
+        assert_eq!(OctTree::decode(&bytes).err(), Some(DecodeError::InvalidTree));
+        for levels in [0, 32, u32::MAX] {
+            let bytes = with_header(levels, 1, 0, &[leaf]);
+            assert_eq!(OctTree::decode(&bytes).err(), Some(DecodeError::InvalidTree));
+        }
+        let bytes = OctTree::from_leaf(1, 3).encode(Compression::Rle);
+        assert!(OctTree::decode_levels(&bytes, 3).is_ok());
+        assert_eq!(
+            OctTree::decode_levels(&bytes, 4).err(),
+            Some(DecodeError::UnexpectedLevels(3))
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use nalgebra::Vector3;
+
+use super::{CsgOp, OctTree, OctTreeBuilder};
+
+// positions are in voxels, a voxel is part of the shape when its center is inside
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Box {
+        min: Vector3<f32>,
+        max: Vector3<f32>,
+    },
+    Sphere {
+        center: Vector3<f32>,
+        radius: f32,
+    },
+    // flat caps at a and b
+    Cylinder {
+        a: Vector3<f32>,
+        b: Vector3<f32>,
+        radius: f32,
+    },
+    // round caps at a and b
+    Capsule {
+        a: Vector3<f32>,
+        b: Vector3<f32>,
+        radius: f32,
+    },
+    // everything at most dist along normal, so normal points away from the solid side
+    HalfSpace {
+        normal: Vector3<f32>,
+        dist: f32,
+    },
+}
+
+impl Shape {
+    // signed distance to the surface, negative inside; it never changes faster than
+    // the distance moved, which is what lets whole nodes be skipped
+    pub fn distance(&self, p: Vector3<f32>) -> f32 {
+        match *self {
+            Shape::Box { min, max } => {
+                let q = (p - (min + max) * 0.5).abs() - (max - min) * 0.5;
+                q.sup(&Vector3::zeros()).norm() + q.max().min(0.0)
+            }
+            Shape::Sphere { center, radius } => (p - center).norm() - radius,
+            Shape::Cylinder { a, b, radius } => {
+                let (ba, pa) = (b - a, p - a);
+                let baba = ba.dot(&ba);
+                let paba = pa.dot(&ba);
+                let x = (pa * baba - ba * paba).norm() - radius * baba;
+                let y = (paba - baba * 0.5).abs() - baba * 0.5;
+                let (x2, y2) = (x * x, y * y * baba);
+                let d = if x.max(y) < 0.0 {
+                    -x2.min(y2)
+                } else {
+                    let x2 = if x > 0.0 { x2 } else { 0.0 };
+                    let y2 = if y > 0.0 { y2 } else { 0.0 };
+                    x2 + y2
+                };
+                d.signum() * d.abs().sqrt() / baba
+            }
+            Shape::Capsule { a, b, radius } => {
+                let (ba, pa) = (b - a, p - a);
+                // a == b is just a sphere
+                let h = (pa.dot(&ba) / ba.dot(&ba).max(f32::EPSILON)).clamp(0.0, 1.0);
+                (pa - ba * h).norm() - radius
+            }
+            Shape::HalfSpace { normal, dist } => p.dot(&normal.normalize()) - dist,
+        }
+    }
+    // false if any parameter isn't finite or the shape is degenerate, distance()
+    // returns NaN for those so nothing could be pruned when building them
+    pub fn is_valid(&self) -> bool {
+        let finite = |v: &Vector3<f32>| v.iter().all(|c| c.is_finite());
+        match self {
+            Shape::Box { min, max } => finite(min) && finite(max),
+            Shape::Sphere { center, radius } => {
+                finite(center) && radius.is_finite() && *radius > 0.0
+            }
+            Shape::Cylinder { a, b, radius } => {
+                finite(a) && finite(b) && a != b && radius.is_finite() && *radius > 0.0
+            }
+            Shape::Capsule { a, b, radius } => {
+                finite(a) && finite(b) && radius.is_finite() && *radius > 0.0
+            }
+            Shape::HalfSpace { normal, dist } => {
+                finite(normal) && *normal != Vector3::zeros() && dist.is_finite()
+            }
+        }
+    }
+    // the same shape moved by offset
+    pub fn translated(&self, offset: Vector3<f32>) -> Self {
+        match *self {
+            Shape::Box { min, max } => Shape::Box {
+                min: min + offset,
+                max: max + offset,
+            },
+            Shape::Sphere { center, radius } => Shape::Sphere {
+                center: center + offset,
+                radius,
+            },
+            Shape::Cylinder { a, b, radius } => Shape::Cylinder {
+                a: a + offset,
+                b: b + offset,
+                radius,
+            },
+            Shape::Capsule { a, b, radius } => Shape::Capsule {
+                a: a + offset,
+                b: b + offset,
+                radius,
+            },
+            Shape::HalfSpace { normal, dist } => Shape::HalfSpace {
+                normal,
+                dist: dist + offset.dot(&normal.normalize()),
+            },
+        }
+    }
+    // a box the whole shape is in, None for half spaces
+    pub fn bounds(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        match *self {
+            Shape::Box { min, max } => Some((min.inf(&max), min.sup(&max))),
+            Shape::Sphere { center, radius } => {
+                let r = Vector3::from_element(radius);
+                Some((center - r, center + r))
+            }
+            Shape::Cylinder { a, b, radius } | Shape::Capsule { a, b, radius } => {
+                let r = Vector3::from_element(radius);
+                Some((a.inf(&b) - r, a.sup(&b) + r))
+            }
+            Shape::HalfSpace { .. } => None,
+        }
+    }
+}
+
+impl OctTree {
+    // the shape filled with material, everything else is 0
+    pub fn from_shape(shape: &Shape, material: u32, levels: u32) -> Self {
+        OctTreeBuilder::from_shape(shape, material, levels).build()
+    }
+    // combines the shape into a copy of this tree, eg. Union to place it or Subtract to carve it;
+    // bounded shapes only get built over the box they cover, which is then stamped in
+    pub fn with_shape(&self, shape: &Shape, material: u32, op: CsgOp) -> Self {
+        let Some((min, max)) = shape.bounds() else {
+            return self.csg(&Self::from_shape(shape, material, self.levels), op);
+        };
+        let side = self.side_length as f32;
+        let min = min.map(|c| c.floor().clamp(0.0, side) as usize);
+        let max = max.map(|c| c.ceil().clamp(0.0, side) as usize);
+        let size = (max - min).max().max(2);
+        let levels = size.next_power_of_two().trailing_zeros().min(self.levels);
+        let shape = shape.translated(-min.cast::<f32>());
+        self.stamp(&Self::from_shape(&shape, material, levels), min, op)
+    }
+}
+
+impl OctTreeBuilder {
+    pub fn from_shape(shape: &Shape, material: u32, levels: u32) -> Self {
+        Self::from_fn(
+            &mut |p| {
+                let center = p.cast::<f32>() + Vector3::from_element(0.5);
+                if shape.distance(center) <= 0.0 {
+                    material
+                } else {
+                    0
+                }
+            },
+            &mut |p, lvl| {
+                // the voxel centers of the node are all within reach of its middle
+                let size = 2usize.pow(lvl) as f32;
+                let center = p.cast::<f32>() + Vector3::from_element(size * 0.5);
+                let reach = 3f32.sqrt() * (size - 1.0) * 0.5;
+                let d = shape.distance(center);
+                if d > reach {
+                    Some(0)
+                } else if d <= -reach {
+                    Some(material)
+                } else {
+                    None
+                }
+            },
+            levels,
+        )
+    }
+}
@@ -0,0 +1,140 @@
+use std::{collections::BTreeMap, fmt};
+
+use rustc_hash::FxHashSet;
+
+use super::{children, OctNode, OctNodes, OctTree};
+
+// what a tree looks like in memory, everything is counted as stored so a group that's
+// shared by many parents only counts once per depth it shows up at
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OctTreeStats {
+    // indexed by depth, the root is at depth 0
+    pub levels: Vec<LevelStats>,
+    // distinct groups of 8 children in the node array
+    pub groups: usize,
+    // groups with more than one parent pointing at them
+    pub shared_groups: usize,
+    // pointers that reuse a group instead of storing a copy of it
+    pub dedup_hits: usize,
+    // number of leaf nodes for each value
+    pub leaf_values: BTreeMap<u32, usize>,
+    pub max_depth: u32,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LevelStats {
+    pub groups: usize,
+    pub leaves: usize,
+}
+
+impl OctTree {
+    pub fn stats(&self) -> OctTreeStats {
+        let nodes =
+            OctNodes::from_raw(self.data.to_vec(), self.levels).expect("trees are always valid");
+        let mut stats = OctTreeStats {
+            groups: nodes.map.len(),
+            shared_groups: nodes.map.values().filter(|e| e.count > 1).count(),
+            dedup_hits: nodes.map.values().map(|e| e.count as usize - 1).sum(),
+            bytes: std::mem::size_of_val(self.raw()),
+            ..Default::default()
+        };
+        let root = self.data[0];
+        stats.levels.push(LevelStats {
+            groups: root.is_node() as usize,
+            leaves: root.is_leaf() as usize,
+        });
+        if root.is_leaf() {
+            stats.leaf_values.insert(root.leaf_data(), 1);
+        }
+        let mut groups: FxHashSet<OctNode> = root.is_node().then_some(root).into_iter().collect();
+        while !groups.is_empty() {
+            let mut level = LevelStats::default();
+            let mut next = FxHashSet::default();
+            for group in groups {
+                for child in children(&self.data, group) {
+                    if child.is_leaf() {
+                        level.leaves += 1;
+                        *stats.leaf_values.entry(child.leaf_data()).or_default() += 1;
+                    } else {
+                        next.insert(child);
+                    }
+                }
+            }
+            level.groups = next.len();
+            stats.levels.push(level);
+            groups = next;
+        }
+        stats.max_depth = stats.levels.len() as u32 - 1;
+        stats
+    }
+}
+
+impl fmt::Display for OctTreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} bytes, {} groups ({} shared, {} dedup hits), max depth {}",
+            self.bytes, self.groups, self.shared_groups, self.dedup_hits, self.max_depth
+        )?;
+        for (depth, level) in self.levels.iter().enumerate() {
+            writeln!(
+                f,
+                "  depth {:>2}: {:>8} groups {:>8} leaves",
+                depth, level.groups, level.leaves
+            )?;
+        }
+        write!(f, "  leaf values:")?;
+        for (val, count) in &self.leaf_values {
+            write!(f, " {}x{}", val, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ndarray::Array3;
+
+    use super::*;
+
+    #[test]
+    fn known_tree() {
+        // one octant all 1s, two octants sharing the same group with a single 2 in it
+        let arr = Array3::from_shape_fn((4, 4, 4), |(x, y, z)| match (x / 2, y / 2, z / 2) {
+            (0, 0, 0) => 1,
+            (0, 0, 1) | (0, 1, 0) => ((x, y % 2, z % 2) == (0, 0, 0)) as u32 * 2,
+            _ => 0,
+        });
+        let stats = OctTree::from_arr(arr.view(), 2).stats();
+        let level = |groups, leaves| LevelStats { groups, leaves };
+        assert_eq!(stats.levels, vec![level(1, 0), level(1, 6), level(0, 8)]);
+        assert_eq!(stats.groups, 2);
+        assert_eq!(stats.shared_groups, 1);
+        assert_eq!(stats.dedup_hits, 1);
+        assert_eq!(stats.leaf_values, BTreeMap::from([(0, 12), (1, 1), (2, 1)]));
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.bytes, (1 + 2 * 8) * 4);
+    }
+
+    #[test]
+    fn uniform_tree() {
+        let stats = OctTree::from_leaf(3, 4).stats();
+        assert_eq!(
+            stats.levels,
+            vec![LevelStats {
+                groups: 0,
+                leaves: 1
+            }]
+        );
+        assert_eq!(
+            (stats.groups, stats.shared_groups, stats.dedup_hits),
+            (0, 0, 0)
+        );
+        assert_eq!(stats.leaf_values, BTreeMap::from([(3, 1)]));
+        assert_eq!(stats.max_depth, 0);
+        assert_eq!(stats.bytes, 4);
+    }
+}
@@ -0,0 +1,125 @@
+use nalgebra::Vector3;
+use rustc_hash::FxHashMap;
+
+use super::{children, find_leaf, OctNode, OctNodes, OctTree};
+
+// One deduplicated node array shared by many trees (eg. every loaded chunk), so identical
+// subtrees like all water or all stone blocks are only stored once.
+// Addresses stay valid until the stored tree is removed, which means the array can be
+// mirrored into a gpu buffer by uploading whatever got appended after each insert.
+#[derive(Debug, Clone)]
+pub struct OctStore {
+    nodes: OctNodes,
+    tree_bytes: usize,
+    trees: usize,
+}
+
+// a tree that lives in an OctStore; root is always a group of children
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoredTree {
+    pub root: OctNode,
+    pub levels: u32,
+    bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreMemory {
+    pub trees: usize,
+    // what the same trees take up when each one has its own node array
+    pub separate_bytes: usize,
+    pub shared_bytes: usize,
+}
+
+impl StoreMemory {
+    pub fn saved_bytes(&self) -> isize {
+        self.separate_bytes as isize - self.shared_bytes as isize
+    }
+}
+
+impl OctStore {
+    pub fn new() -> Self {
+        Self {
+            // address 0 is never a group so it can't be confused with an empty pointer
+            nodes: OctNodes {
+                data: vec![OctNode::new_leaf(0)],
+                ..Default::default()
+            },
+            tree_bytes: 0,
+            trees: 0,
+        }
+    }
+    pub fn insert(&mut self, tree: &OctTree) -> StoredTree {
+        let data = &tree.data;
+        let mut moved = FxHashMap::default();
+        let root = children(data, data[0]).map(|c| self.nodes.copy_from(data, c, &mut moved));
+        let root = self.nodes.intern_group(root);
+        let bytes = tree_bytes(tree);
+        self.tree_bytes += bytes;
+        self.trees += 1;
+        StoredTree {
+            root,
+            levels: tree.levels,
+            bytes,
+        }
+    }
+    pub fn remove(&mut self, tree: StoredTree) {
+        self.nodes.release(tree.root);
+        self.tree_bytes -= tree.bytes;
+        self.trees -= 1;
+    }
+    pub fn get(&self, tree: &StoredTree, pos: Vector3<usize>) -> u32 {
+        find_leaf(&self.nodes.data, tree.root, tree.levels, pos).val
+    }
+    pub fn to_tree(&self, tree: &StoredTree) -> OctTree {
+        let mut nodes = OctNodes::default();
+        nodes.data.push(tree.root);
+        let mut moved = FxHashMap::default();
+        nodes.data[0] = nodes.copy_from(&self.nodes.data, tree.root, &mut moved);
+        OctTree {
+            data: nodes.data.into(),
+            side_length: 2usize.pow(tree.levels),
+            levels: tree.levels,
+        }
+    }
+    pub fn raw(&self) -> &[OctNode] {
+        &self.nodes.data
+    }
+    // bytes of groups that no stored tree uses anymore
+    pub fn wasted_bytes(&self) -> usize {
+        self.nodes.garbage * std::mem::size_of::<OctNode>()
+    }
+    // rebuilds the node array with only the groups of the given trees and moves their roots,
+    // so it has to be given every tree that's still in the store; returns the bytes reclaimed
+    pub fn compact<'a>(&mut self, trees: impl IntoIterator<Item = &'a mut StoredTree>) -> usize {
+        let old = std::mem::replace(&mut self.nodes, Self::new().nodes);
+        let mut moved = FxHashMap::default();
+        for tree in trees {
+            let root = children(&old.data, tree.root)
+                .map(|c| self.nodes.copy_from(&old.data, c, &mut moved));
+            tree.root = self.nodes.intern_group(root);
+        }
+        self.nodes.data.shrink_to_fit();
+        (old.data.len() - self.nodes.data.len()) * std::mem::size_of::<OctNode>()
+    }
+    pub fn memory(&self) -> StoreMemory {
+        let live = self.nodes.data.len() - self.nodes.garbage;
+        StoreMemory {
+            trees: self.trees,
+            separate_bytes: self.tree_bytes,
+            shared_bytes: live * std::mem::size_of::<OctNode>()
+                + self.nodes.map.len() * MAP_ENTRY_BYTES,
+        }
+    }
+}
+
+impl Default for OctStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const MAP_ENTRY_BYTES: usize = std::mem::size_of::<([OctNode; 8], super::OctNodeEntry)>();
+
+fn tree_bytes(tree: &OctTree) -> usize {
+    std::mem::size_of_val(tree.raw())
+}
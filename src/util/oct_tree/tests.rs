@@ -0,0 +1,593 @@
+use nalgebra::Vector3;
+use ndarray::Array3;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::common::component::chunk;
+
+use super::*;
+
+// levels a dense array is still small enough to compare voxel by voxel
+const DENSE_LEVELS: u32 = 5;
+
+// OctTreeIter goes through the children of a node in CORNERS order,
+// so the i-th value is at the position whose bits interleave as ..xyzxyz
+fn iter_pos(i: usize, levels: u32) -> Vector3<usize> {
+    let mut pos = Vector3::zeros();
+    for bit in 0..levels as usize {
+        pos.x |= ((i >> (bit * 3 + 2)) & 1) << bit;
+        pos.y |= ((i >> (bit * 3 + 1)) & 1) << bit;
+        pos.z |= ((i >> (bit * 3)) & 1) << bit;
+    }
+    pos
+}
+
+fn check_arr(tree: &OctTree, arr: &Array3<u32>) {
+    let n = arr.shape()[0];
+    for ((x, y, z), val) in arr.indexed_iter() {
+        assert_eq!(tree.get(Vector3::new(x, y, z)), *val, "get at {x},{y},{z}");
+    }
+    let mut count = 0;
+    for (i, val) in tree.into_iter().enumerate() {
+        let p = iter_pos(i, tree.levels());
+        assert_eq!(val, arr[(p.x, p.y, p.z)], "iter at {p}");
+        count += 1;
+    }
+    assert_eq!(count, n.pow(3));
+    // the leaves cover every voxel exactly once and are uniform
+    let mut covered = Array3::from_elem(arr.dim(), false);
+    for leaf in tree.leaves() {
+        let (p, s) = (leaf.pos, leaf.size());
+        for x in p.x..p.x + s {
+            for y in p.y..p.y + s {
+                for z in p.z..p.z + s {
+                    assert!(!covered[(x, y, z)], "leaves overlap at {x},{y},{z}");
+                    assert_eq!(arr[(x, y, z)], leaf.val, "leaf at {p}");
+                    covered[(x, y, z)] = true;
+                }
+            }
+        }
+    }
+    assert!(covered.iter().all(|c| *c), "leaves leave gaps");
+}
+
+// every reachable group is in the dedup map with the right reference count,
+// appears only once and isn't 8 identical leaves that should have been collapsed
+fn check_nodes(builder: &OctTreeBuilder) {
+    let nodes = &builder.nodes;
+    let mut counts = FxHashMap::<[OctNode; 8], u32>::default();
+    let mut addrs = FxHashMap::<[OctNode; 8], u32>::default();
+    let mut seen = FxHashSet::default();
+    let mut stack = vec![nodes.data[0]];
+    if nodes.data[0].is_node() {
+        *counts.entry(nodes.children(nodes.data[0])).or_default() += 1;
+    }
+    while let Some(node) = stack.pop() {
+        if node.is_leaf() || !seen.insert(node) {
+            continue;
+        }
+        let group = nodes.children(node);
+        assert!(
+            !(group[0].is_leaf() && group.iter().all(|c| *c == group[0])),
+            "uniform group at {}",
+            node.node_data()
+        );
+        let addr = addrs.entry(group).or_insert(node.node_data());
+        assert_eq!(*addr, node.node_data(), "group stored twice");
+        for child in group {
+            if child.is_node() {
+                *counts.entry(nodes.children(child)).or_default() += 1;
+                stack.push(child);
+            }
+        }
+    }
+    assert_eq!(counts.len(), nodes.map.len(), "map has unreachable groups");
+    assert_eq!(
+        nodes.data.len(),
+        1 + counts.len() * 8 + nodes.garbage,
+        "garbage count is off"
+    );
+    for (group, count) in counts {
+        let entry = nodes
+            .map
+            .get(&group)
+            .expect("reachable group missing from map");
+        assert_eq!(entry.count, count, "refcount of {}", entry.node.node_data());
+    }
+}
+
+fn random_arr(rng: &mut StdRng, levels: u32, vals: u32) -> Array3<u32> {
+    let n = 2usize.pow(levels);
+    Array3::from_shape_fn((n, n, n), |_| rng.gen_range(0..vals))
+}
+
+// a sphere sitting in the middle of a floor, with a few layers of stripes above it
+struct Structured {
+    center: Vector3<i64>,
+    radius: i64,
+    floor: usize,
+    stripe: usize,
+}
+
+impl Structured {
+    fn new(levels: u32) -> Self {
+        let n = 2i64.pow(levels);
+        Self {
+            center: Vector3::new(n / 2 + 1, n / 3, n / 2 - 1),
+            radius: n / 4,
+            floor: n as usize / 3,
+            stripe: (n as usize / 16).max(1),
+        }
+    }
+    fn get(&self, p: Vector3<usize>) -> u32 {
+        let d = p.cast::<i64>() - self.center;
+        if d.dot(&d) <= self.radius.pow(2) {
+            2
+        } else if p.y < self.floor {
+            1
+        } else {
+            (p.y / self.stripe).is_multiple_of(2) as u32 * 3
+        }
+    }
+    fn node(&self, pos: Vector3<usize>, level: u32) -> Option<u32> {
+        let s = 2usize.pow(level);
+        let min = pos.cast::<i64>() - self.center;
+        let max = min + Vector3::from_element(s as i64 - 1);
+        let near = min.zip_map(&max, |a, b| {
+            if a > 0 {
+                a
+            } else if b < 0 {
+                b
+            } else {
+                0
+            }
+        });
+        let far = min.zip_map(&max, |a, b| a.abs().max(b.abs()));
+        let r = self.radius.pow(2);
+        if far.dot(&far) <= r {
+            return Some(2);
+        }
+        if near.dot(&near) <= r {
+            return None;
+        }
+        if pos.y + s <= self.floor {
+            Some(1)
+        } else if pos.y >= self.floor && pos.y / self.stripe == (pos.y + s - 1) / self.stripe {
+            Some(self.get(pos))
+        } else {
+            None
+        }
+    }
+    fn arr(&self, levels: u32) -> Array3<u32> {
+        let n = 2usize.pow(levels);
+        Array3::from_shape_fn((n, n, n), |(x, y, z)| self.get(Vector3::new(x, y, z)))
+    }
+}
+
+#[test]
+fn random_arrays() {
+    let mut rng = StdRng::seed_from_u64(1);
+    for levels in 1..=DENSE_LEVELS {
+        for vals in [1, 2, 5, 1000] {
+            let arr = random_arr(&mut rng, levels, vals);
+            let builder = OctTreeBuilder::from_arr(arr.view(), levels);
+            check_nodes(&builder);
+            check_arr(&builder.build(), &arr);
+            check_arr(&OctTree::from_arr(arr.view(), levels), &arr);
+        }
+    }
+}
+
+#[test]
+fn dedup_keeps_values() {
+    // 2x2x2 blocks copied all over the array, so most groups get shared
+    let mut rng = StdRng::seed_from_u64(2);
+    for levels in 2..=DENSE_LEVELS {
+        let blocks: Vec<_> = (0..3).map(|_| random_arr(&mut rng, 1, 3)).collect();
+        let n = 2usize.pow(levels);
+        let picks = Array3::from_shape_fn((n / 2, n / 2, n / 2), |_| rng.gen_range(0..3));
+        let arr = Array3::from_shape_fn((n, n, n), |(x, y, z)| {
+            blocks[picks[(x / 2, y / 2, z / 2)]][(x % 2, y % 2, z % 2)]
+        });
+        let builder = OctTreeBuilder::from_arr(arr.view(), levels);
+        check_nodes(&builder);
+        check_arr(&builder.build(), &arr);
+    }
+}
+
+#[test]
+fn structured_arrays() {
+    for levels in 1..=DENSE_LEVELS {
+        let s = Structured::new(levels);
+        let arr = s.arr(levels);
+        let builder = OctTreeBuilder::from_arr(arr.view(), levels);
+        check_nodes(&builder);
+        check_arr(&builder.build(), &arr);
+        // skipping uniform nodes has to give the exact same tree
+        let pruned = OctTree::from_fn(&mut |p| s.get(p), &mut |p, l| s.node(p, l), levels);
+        assert_eq!(pruned.raw(), builder.raw());
+        let par = OctTree::from_fn_par(&|p| s.get(p), &|p, l| s.node(p, l), levels, 1);
+        assert_eq!(par.raw(), builder.raw());
+    }
+}
+
+#[test]
+fn structured_up_to_chunk_scale() {
+    let mut rng = StdRng::seed_from_u64(3);
+    for levels in 1..=chunk::SCALE {
+        let s = Structured::new(levels);
+        let n = 2usize.pow(levels);
+        let builder = OctTreeBuilder::from_fn(&mut |p| s.get(p), &mut |p, l| s.node(p, l), levels);
+        check_nodes(&builder);
+        let tree = builder.build();
+        for _ in 0..10_000 {
+            let p = Vector3::from_fn(|_, _| rng.gen_range(0..n));
+            assert_eq!(tree.get(p), s.get(p), "get at {p}");
+        }
+        let mut volume = 0;
+        for leaf in tree.leaves() {
+            let size = leaf.size();
+            volume += size.pow(3);
+            for corner in CORNERS {
+                let p = leaf.pos + corner * (size - 1);
+                assert_eq!(leaf.val, s.get(p), "leaf corner at {p}");
+            }
+        }
+        assert_eq!(volume, n.pow(3));
+    }
+}
+
+#[test]
+fn edits_and_compaction() {
+    let mut rng = StdRng::seed_from_u64(4);
+    for levels in 1..=DENSE_LEVELS {
+        let n = 2usize.pow(levels);
+        let mut arr = Structured::new(levels).arr(levels);
+        let mut builder = OctTreeBuilder::from_arr(arr.view(), levels);
+        // compacting by hand as well as automatically after some of the edits
+        builder.set_compact_threshold(if levels % 2 == 0 { 0.5 } else { 2.0 });
+        for i in 0..200 {
+            let val = rng.gen_range(0..4);
+            if rng.gen_bool(0.5) {
+                let p = Vector3::from_fn(|_, _| rng.gen_range(0..n));
+                builder.set(p, val);
+                arr[(p.x, p.y, p.z)] = val;
+            } else {
+                let min = Vector3::from_fn(|_, _| rng.gen_range(0..n));
+                let max = min + Vector3::from_fn(|_, _| rng.gen_range(0..=n));
+                builder.set_region(min, max, val);
+                for ((x, y, z), v) in arr.indexed_iter_mut() {
+                    let p = Vector3::new(x, y, z);
+                    if (0..3).all(|i| p[i] >= min[i] && p[i] < max[i]) {
+                        *v = val;
+                    }
+                }
+            }
+            check_nodes(&builder);
+            if i % 20 == 0 {
+                builder.compact();
+                assert_eq!(builder.wasted_bytes(), 0);
+                check_nodes(&builder);
+            }
+            if i % 10 == 0 {
+                check_arr(&builder.build(), &arr);
+            }
+        }
+        // after compacting, an edited tree holds the same groups as one built from scratch
+        builder.compact();
+        let fresh = OctTreeBuilder::from_arr(arr.view(), levels);
+        assert_eq!(builder.nodes.map.len(), fresh.nodes.map.len());
+        assert_eq!(builder.raw().len(), fresh.raw().len());
+        check_arr(&builder.build(), &arr);
+    }
+}
+
+#[test]
+fn builder_round_trip() {
+    let mut rng = StdRng::seed_from_u64(5);
+    for levels in 1..=DENSE_LEVELS {
+        let arr = random_arr(&mut rng, levels, 3);
+        let tree = OctTree::from_arr(arr.view(), levels);
+        let builder = tree.to_builder();
+        check_nodes(&builder);
+        assert_eq!(builder.build().raw(), tree.raw());
+    }
+}
+
+fn diff_size(diff: &OctDiff) -> usize {
+    diff.changes
+        .iter()
+        .map(|c| match c {
+            OctChange::Fill { .. } => 1,
+            OctChange::Replace { tree, .. } => tree.raw().len(),
+        })
+        .sum()
+}
+
+#[test]
+fn diff_and_apply() {
+    let mut rng = StdRng::seed_from_u64(6);
+    for levels in 1..=DENSE_LEVELS {
+        let n = 2usize.pow(levels);
+        let old = Structured::new(levels).arr(levels);
+        let old_tree = OctTree::from_arr(old.view(), levels);
+        for _ in 0..20 {
+            let mut builder = old_tree.to_builder();
+            for _ in 0..rng.gen_range(1..4) {
+                let min = Vector3::from_fn(|_, _| rng.gen_range(0..n));
+                let max = min + Vector3::from_fn(|_, _| rng.gen_range(1..=n / 2 + 1));
+                builder.set_region(min, max, rng.gen_range(0..4));
+            }
+            let new_tree = builder.build();
+            let diff = OctTree::diff(&old_tree, &new_tree);
+            let applied = old_tree.apply(&diff);
+            let new: Array3<u32> =
+                Array3::from_shape_fn(old.dim(), |(x, y, z)| new_tree.get(Vector3::new(x, y, z)));
+            check_arr(&applied, &new);
+            // diffing trees that don't share addresses has to give the same result
+            let rebuilt = OctTree::from_arr(new.view(), levels);
+            check_arr(&old_tree.apply(&OctTree::diff(&old_tree, &rebuilt)), &new);
+        }
+    }
+}
+
+#[test]
+fn small_edit_small_diff() {
+    // an edit in the middle touches all 8 children of the root
+    let levels = 7;
+    let s = Structured::new(levels);
+    let old = OctTree::from_fn(&mut |p| s.get(p), &mut |p, l| s.node(p, l), levels);
+    let mut builder = old.to_builder();
+    let center = Vector3::from_element(2usize.pow(levels) / 2);
+    builder.set_region(center.map(|c| c - 2), center.map(|c| c + 2), 7);
+    let new = builder.build();
+    let diff = OctTree::diff(&old, &new);
+    assert!(diff_size(&diff) <= 8, "{} nodes", diff_size(&diff));
+    assert!(OctTree::diff(&old.apply(&diff), &new).is_empty());
+}
+
+fn csg_voxel(a: u32, b: u32, op: CsgOp) -> u32 {
+    match (op, b != 0) {
+        (CsgOp::Union, true) => b,
+        (CsgOp::Subtract, true) | (CsgOp::Intersect, false) => 0,
+        (CsgOp::Union, false) | (CsgOp::Subtract, false) | (CsgOp::Intersect, true) => a,
+    }
+}
+
+const OPS: [CsgOp; 3] = [CsgOp::Union, CsgOp::Subtract, CsgOp::Intersect];
+
+// sparse random voxels, the structured scene, an edited copy of it that shares most of its
+// groups with the original, or a single value
+fn csg_input(rng: &mut StdRng, levels: u32) -> Array3<u32> {
+    let n = 2usize.pow(levels);
+    match rng.gen_range(0..4) {
+        0 => Array3::from_shape_fn((n, n, n), |_| {
+            rng.gen_bool(0.2) as u32 * rng.gen_range(1..4)
+        }),
+        1 => Structured::new(levels).arr(levels),
+        2 => {
+            let mut arr = Structured::new(levels).arr(levels);
+            let min = Vector3::from_fn(|_, _| rng.gen_range(0..n));
+            let size = Vector3::from_fn(|_, _| rng.gen_range(1..=n / 2 + 1));
+            let val = rng.gen_range(0..4);
+            for ((x, y, z), v) in arr.indexed_iter_mut() {
+                let p = Vector3::new(x, y, z);
+                if (0..3).all(|i| p[i] >= min[i] && p[i] < min[i] + size[i]) {
+                    *v = val;
+                }
+            }
+            arr
+        }
+        _ => Array3::from_elem((n, n, n), rng.gen_range(0..3)),
+    }
+}
+
+#[test]
+fn csg_matches_dense() {
+    let mut rng = StdRng::seed_from_u64(7);
+    for levels in 1..=DENSE_LEVELS {
+        for _ in 0..10 {
+            let (a, b) = (csg_input(&mut rng, levels), csg_input(&mut rng, levels));
+            let (ta, tb) = (
+                OctTree::from_arr(a.view(), levels),
+                OctTree::from_arr(b.view(), levels),
+            );
+            for op in OPS {
+                let expected = Array3::from_shape_fn(a.dim(), |i| csg_voxel(a[i], b[i], op));
+                let tree = ta.csg(&tb, op);
+                check_nodes(&tree.to_builder());
+                check_arr(&tree, &expected);
+            }
+        }
+    }
+}
+
+#[test]
+fn stamp_matches_dense() {
+    let mut rng = StdRng::seed_from_u64(8);
+    for levels in 1..=DENSE_LEVELS {
+        let n = 2usize.pow(levels);
+        for _ in 0..20 {
+            let other_levels = rng.gen_range(1..=levels);
+            let m = 2usize.pow(other_levels);
+            let (a, b) = (
+                csg_input(&mut rng, levels),
+                csg_input(&mut rng, other_levels),
+            );
+            // lined up with the other tree's nodes or not, and sometimes hanging off the edge
+            let offset = if rng.gen_bool(0.5) {
+                Vector3::from_fn(|_, _| rng.gen_range(0..n / m) * m)
+            } else {
+                Vector3::from_fn(|_, _| rng.gen_range(0..n))
+            };
+            let (ta, tb) = (
+                OctTree::from_arr(a.view(), levels),
+                OctTree::from_arr(b.view(), other_levels),
+            );
+            for op in OPS {
+                let expected = Array3::from_shape_fn(a.dim(), |(x, y, z)| {
+                    let p = Vector3::new(x, y, z);
+                    let inside = (0..3).all(|i| p[i] >= offset[i] && p[i] < offset[i] + m);
+                    let other = if inside {
+                        let q = p - offset;
+                        b[(q.x, q.y, q.z)]
+                    } else {
+                        0
+                    };
+                    csg_voxel(a[(x, y, z)], other, op)
+                });
+                let tree = ta.stamp(&tb, offset, op);
+                check_nodes(&tree.to_builder());
+                check_arr(&tree, &expected);
+            }
+        }
+    }
+}
+
+fn random_point(rng: &mut StdRng, n: usize) -> Vector3<f32> {
+    let n = n as f32;
+    Vector3::from_fn(|_, _| rng.gen_range(-0.5 * n..1.5 * n))
+}
+
+fn random_shape(rng: &mut StdRng, n: usize) -> Shape {
+    let radius = rng.gen_range(0.3..n as f32 * 0.6);
+    let (a, b) = (random_point(rng, n), random_point(rng, n));
+    match rng.gen_range(0..5) {
+        0 => Shape::Box { min: a, max: b },
+        1 => Shape::Sphere { center: a, radius },
+        2 => Shape::Cylinder { a, b, radius },
+        3 => Shape::Capsule { a, b, radius },
+        _ => Shape::HalfSpace {
+            normal: random_point(rng, n),
+            dist: rng.gen_range(-(n as f32)..n as f32),
+        },
+    }
+}
+
+// voxels with their center in the shape are material
+fn shape_arr(shape: &Shape, material: u32, levels: u32) -> Array3<u32> {
+    let n = 2usize.pow(levels);
+    Array3::from_shape_fn((n, n, n), |(x, y, z)| {
+        let center = Vector3::new(x, y, z).cast::<f32>() + Vector3::from_element(0.5);
+        (shape.distance(center) <= 0.0) as u32 * material
+    })
+}
+
+#[test]
+fn with_shape_matches_dense() {
+    let mut rng = StdRng::seed_from_u64(9);
+    for levels in 1..=DENSE_LEVELS {
+        let n = 2usize.pow(levels);
+        for _ in 0..30 {
+            let shape = random_shape(&mut rng, n);
+            if !shape.is_valid() {
+                continue;
+            }
+            let material = rng.gen_range(1..4);
+            let filled = shape_arr(&shape, material, levels);
+            let a = csg_input(&mut rng, levels);
+            let ta = OctTree::from_arr(a.view(), levels);
+            for op in OPS {
+                let expected = Array3::from_shape_fn(a.dim(), |i| csg_voxel(a[i], filled[i], op));
+                let tree = ta.with_shape(&shape, material, op);
+                check_nodes(&tree.to_builder());
+                check_arr(&tree, &expected);
+            }
+        }
+    }
+}
+
+#[test]
+fn shapes_match_dense() {
+    let mut rng = StdRng::seed_from_u64(10);
+    for levels in 1..=DENSE_LEVELS {
+        let n = 2usize.pow(levels);
+        for _ in 0..30 {
+            let shape = random_shape(&mut rng, n);
+            if !shape.is_valid() {
+                continue;
+            }
+            let material = rng.gen_range(1..4);
+            let tree = OctTree::from_shape(&shape, material, levels);
+            check_nodes(&tree.to_builder());
+            check_arr(&tree, &shape_arr(&shape, material, levels));
+        }
+    }
+}
+
+#[test]
+fn shapes_up_to_chunk_scale() {
+    // whole nodes get skipped by their distance to the surface, which has to agree with
+    // testing every voxel center on its own
+    let mut rng = StdRng::seed_from_u64(11);
+    for levels in DENSE_LEVELS + 1..=chunk::SCALE {
+        let n = 2usize.pow(levels);
+        for _ in 0..3 {
+            let shape = random_shape(&mut rng, n);
+            if !shape.is_valid() {
+                continue;
+            }
+            let voxel = |p: Vector3<usize>| {
+                let center = p.cast::<f32>() + Vector3::from_element(0.5);
+                (shape.distance(center) <= 0.0) as u32
+            };
+            let builder = OctTreeBuilder::from_shape(&shape, 1, levels);
+            check_nodes(&builder);
+            let tree = builder.build();
+            for _ in 0..10_000 {
+                let p = Vector3::from_fn(|_, _| rng.gen_range(0..n));
+                assert_eq!(tree.get(p), voxel(p), "{shape:?} at {p}");
+            }
+            for leaf in tree.leaves().filter(|l| l.size() > 1).take(1000) {
+                for corner in CORNERS {
+                    let p = leaf.pos + corner * (leaf.size() - 1);
+                    assert_eq!(leaf.val, voxel(p), "{shape:?} leaf corner at {p}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn store_matches_trees() {
+    let mut rng = StdRng::seed_from_u64(12);
+    let mut store = OctStore::new();
+    let mut stored: Vec<(StoredTree, Array3<u32>)> = Vec::new();
+    for i in 0..100 {
+        if !stored.is_empty() && rng.gen_bool(0.3) {
+            let (tree, _) = stored.swap_remove(rng.gen_range(0..stored.len()));
+            store.remove(tree);
+        } else if !stored.is_empty() && rng.gen_bool(0.2) {
+            // a copy of a tree that's already there doesn't take up any more nodes
+            let arr = stored[rng.gen_range(0..stored.len())].1.clone();
+            let levels = arr.shape()[0].trailing_zeros();
+            let len = store.raw().len();
+            let tree = store.insert(&OctTree::from_arr(arr.view(), levels));
+            assert_eq!(store.raw().len(), len);
+            stored.push((tree, arr));
+        } else {
+            let levels = rng.gen_range(1..=4);
+            let arr = csg_input(&mut rng, levels);
+            stored.push((store.insert(&OctTree::from_arr(arr.view(), levels)), arr));
+        }
+        if i % 10 == 0 {
+            store.compact(stored.iter_mut().map(|(tree, _)| tree));
+            assert_eq!(store.wasted_bytes(), 0);
+        }
+        assert_eq!(store.memory().trees, stored.len());
+        // right after compacting and right before the next time
+        if i % 10 != 0 && i % 10 != 9 {
+            continue;
+        }
+        for (tree, arr) in &stored {
+            for ((x, y, z), val) in arr.indexed_iter() {
+                assert_eq!(
+                    store.get(tree, Vector3::new(x, y, z)),
+                    *val,
+                    "get at {x},{y},{z}"
+                );
+            }
+            check_arr(&store.to_tree(tree), arr);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::{
     sync::mpsc::{channel, Receiver, Sender, TryIter},
     thread::JoinHandle,
+    time::Duration,
 };
 
 pub trait ExitType {
@@ -64,4 +65,8 @@ impl<SendMsg, RecvMsg> ThreadChannel<SendMsg, RecvMsg> {
     pub fn recv_wait(&self) -> RecvMsg {
         self.recv.recv().expect("OOOAAAAAAA")
     }
+    // None if nothing came in time
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<RecvMsg> {
+        self.recv.recv_timeout(timeout).ok()
+    }
 }